@@ -0,0 +1,137 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands;
+use crate::error::ModuleError;
+use crate::layout::Layout;
+use crate::output::Output;
+use crate::plan::VarFile;
+use crate::repo_config::RepoConfig;
+use crate::resolve::get_module_var_dir;
+use crate::terraform::{Runner, TerraformArgs};
+use crate::Config;
+
+/// Written into the terraform data dir after a successful `condeform init`
+pub const INIT_MARKER_FILENAME: &str = "condeform-init.toml";
+
+/// Records the backend config the data dir was last initialized with.
+#[derive(Deserialize, Serialize, PartialEq)]
+pub struct InitMarker {
+    pub backend_config: VarFile,
+}
+
+/// `.terraform`, or wherever `TF_DATA_DIR` points. Read once by the CLI and passed down, so
+/// that the library doesn't depend on the process environment.
+pub fn data_dir() -> PathBuf {
+    env::var_os("TF_DATA_DIR").map_or(PathBuf::from(".terraform"), PathBuf::from)
+}
+
+/// The selected workspace, as terraform resolves it.
+pub fn workspace(data_dir: &Path) -> String {
+    if let Ok(workspace) = env::var("TF_WORKSPACE") {
+        return workspace;
+    }
+    fs::read_to_string(data_dir.join("environment"))
+        .map(|v| v.trim().to_string())
+        .unwrap_or_else(|_| "default".to_string())
+}
+
+impl InitMarker {
+    /// Whether the data dir was initialized with `backend_config` as it is now. The paths are
+    /// compared canonicalized, as the infra dir may be given relative or absolute.
+    pub fn matches(&self, backend_config: &Path) -> bool {
+        let canonical = |path: &Path| path.canonicalize().ok();
+        canonical(backend_config).is_some()
+            && canonical(backend_config) == canonical(Path::new(&self.backend_config.path))
+            && VarFile::read(backend_config).is_ok_and(|v| v.sha256 == self.backend_config.sha256)
+    }
+}
+
+pub fn write_init_marker(data_dir: &Path, backend_config: &Path) -> anyhow::Result<()> {
+    let marker = InitMarker {
+        backend_config: VarFile::read(&backend_config.canonicalize()?)?,
+    };
+    fs::write(data_dir.join(INIT_MARKER_FILENAME), toml::to_string(&marker)?)?;
+    Ok(())
+}
+
+/// Forgets the backend config, e.g. after a failed init that may have left the data dir half
+/// reconfigured.
+pub fn remove_init_marker(data_dir: &Path) -> io::Result<()> {
+    match fs::remove_file(data_dir.join(INIT_MARKER_FILENAME)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Records the backend config after `terraform init` with it, whether run by `condeform init`
+/// or `condeform tf init`.
+pub fn record_init(
+    data_dir: &Path,
+    status: ExitStatus,
+    backend_config: &Path,
+) -> anyhow::Result<()> {
+    if status.success() {
+        write_init_marker(data_dir, backend_config)
+    } else {
+        Ok(remove_init_marker(data_dir)?)
+    }
+}
+
+pub fn read_init_marker(data_dir: &Path) -> Option<InitMarker> {
+    let str = fs::read_to_string(data_dir.join(INIT_MARKER_FILENAME)).ok()?;
+    toml::from_str(&str).ok()
+}
+
+/// Fails if condeform initialized the data dir with a backend config other than
+/// `backend_config`, or if its content has changed since. A data dir initialized outside
+/// condeform can't be checked, and passes.
+pub fn check_init(data_dir: &Path, backend_config: &Path) -> Result<(), ModuleError> {
+    let marker = match read_init_marker(data_dir) {
+        Some(marker) => marker,
+        None => return Ok(()),
+    };
+    if !marker.matches(backend_config) {
+        return Err(ModuleError::BackendChanged {
+            initialized: marker.backend_config.path,
+            current: backend_config.to_string_lossy().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Refuses to run against a data dir that was initialized with a different backend config,
+/// unless `reinit`, in which case init is run again first. Returns init's status if it failed.
+pub fn check_backend(
+    runner: &dyn Runner,
+    out: &Output,
+    layout: &Layout,
+    repo_config: &RepoConfig,
+    config: &Config,
+    data_dir: &Path,
+    reinit: bool,
+) -> anyhow::Result<Option<ExitStatus>> {
+    let backend_config = get_module_var_dir(layout, config, "backend")?;
+    match check_init(data_dir, &backend_config) {
+        Ok(()) => Ok(None),
+        Err(err) if !reinit => Err(err.into()),
+        Err(_) => {
+            out.message("Backend config has changed since init, re-initializing");
+            let init_status = commands::init(
+                runner,
+                out,
+                layout,
+                repo_config,
+                config,
+                data_dir,
+                &TerraformArgs::default(),
+            )?;
+            Ok((!init_status.success()).then_some(init_status))
+        }
+    }
+}
@@ -1,19 +1,217 @@
-use clap::{Parser, Subcommand, ArgAction};
+use std::env;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ArgAction};
+
+use condeform::output::OutputFormat;
+use condeform::terraform::TerraformArgs;
+use condeform::Config;
+
+use crate::confirm::CONFIRM_FLAG_PREFIX;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(after_help = "Pass --yes-i-mean-<ENV> to apply or destroy a protected environment without a prompt")]
 pub struct Cli {
+    #[command(flatten)]
+    pub overrides: Overrides,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub output: OutputFormat,
     #[command(subcommand)]
     pub command: Commands,
+    /// Protected environments confirmed with `--yes-i-mean-<env>`
+    #[arg(skip)]
+    pub confirmed: Vec<String>,
+}
+
+impl Cli {
+    /// Parses the command line, pulling out `--yes-i-mean-<env>` flags first, as clap can't
+    /// match a flag by prefix. Anything after `--` is left for terraform.
+    pub fn parse_with_confirmations() -> Result<Cli, clap::Error> {
+        let mut confirmed = vec![];
+        let mut args = vec![];
+        let mut passthrough = false;
+        for arg in env::args_os() {
+            if !passthrough {
+                passthrough = arg == "--";
+                if let Some(env) = arg.to_str().and_then(|v| v.strip_prefix(CONFIRM_FLAG_PREFIX)) {
+                    confirmed.push(env.to_string());
+                    continue;
+                }
+            }
+            args.push(arg);
+        }
+
+        Ok(Cli {
+            confirmed,
+            ..Cli::try_parse_from(args)?
+        })
+    }
+}
+
+// Overrides the persisted state for a single invocation
+#[derive(Args)]
+pub struct Overrides {
+    #[arg(long = "env", env = "CONDEFORM_ENV", global = true)]
+    pub environment: Option<String>,
+    #[arg(long, env = "CONDEFORM_REGION", global = true)]
+    pub region: Option<String>,
+    #[arg(long, env = "CONDEFORM_MODULE", global = true)]
+    pub module: Option<String>,
+    #[arg(long, env = "CONDEFORM_INFRA_DIR", global = true)]
+    pub infra_dir: Option<String>,
+    /// Value for a layout segment, e.g. `--segment account=prod-1`
+    #[arg(long = "segment", value_name = "NAME=VALUE", value_parser = parse_segment, global = true)]
+    pub segments: Vec<(String, String)>,
+    /// Terraform executable to run, e.g. `tofu`
+    #[arg(long, env = "CONDEFORM_TERRAFORM_BIN", global = true)]
+    pub terraform_bin: Option<String>,
+    /// Persist the overrides to the module's state
+    #[arg(long, global = true)]
+    pub save: bool,
+    /// Key for the repo's state, instead of the repo's path, e.g. to share state between clones
+    #[arg(long, env = "CONDEFORM_STATE_KEY", global = true)]
+    pub state_key: Option<String>,
+}
+
+impl Overrides {
+    /// Replaces the config's values with any given on the command line or in the environment
+    pub fn apply(&self, mut config: Config) -> Config {
+        for (name, value) in &self.segments {
+            config.set_segment(name, value.to_owned());
+        }
+        Config {
+            environment: self.environment.to_owned().or(config.environment),
+            region: self.region.to_owned().unwrap_or(config.region),
+            module: self.module.to_owned().unwrap_or(config.module),
+            infra_dir: self.infra_dir.to_owned().unwrap_or(config.infra_dir),
+            terraform_bin: self.terraform_bin.to_owned().or(config.terraform_bin),
+            ..config
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct BackendArgs {
+    /// Re-run init if the backend config has changed since the last init
+    #[arg(long)]
+    pub reinit: bool,
+}
+
+/// Plan flags a matrix run doesn't support, as each target has its own data dir and plan
+const MATRIX_CONFLICTS: [&str; 2] = ["reinit", "markdown"];
+
+// Plans each combination of segment values, e.g. every region of an environment
+#[derive(Args)]
+pub struct MatrixArgs {
+    /// Plan every environment in the infra dir
+    #[arg(long, conflicts_with_all = MATRIX_CONFLICTS)]
+    pub all_envs: bool,
+    /// Plan every region of the environment
+    #[arg(long, conflicts_with_all = MATRIX_CONFLICTS)]
+    pub all_regions: bool,
+    /// Segment values to plan, e.g. `--matrix env=stage,prod region=*`
+    #[arg(long, value_name = "NAME=VALUES", value_parser = parse_segment, num_args = 1..)]
+    #[arg(conflicts_with_all = MATRIX_CONFLICTS)]
+    pub matrix: Vec<(String, String)>,
+    /// How many targets to plan at once
+    #[arg(short, long, default_value_t = 4)]
+    pub jobs: usize,
+}
+
+impl MatrixArgs {
+    /// Whether any targets were given, making this a matrix run
+    pub fn is_set(&self) -> bool {
+        self.all_envs || self.all_regions || !self.matrix.is_empty()
+    }
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     Init {
         #[arg(short, long, action = ArgAction::SetTrue)]
-        interactive: Option<bool>
+        interactive: Option<bool>,
+        #[command(flatten)]
+        terraform: TerraformArgs,
     },
     Edit,
-    Plan,
-    Destroy,
+    Plan {
+        /// Exit with 0 for no changes, 1 for errors and 2 for changes, as terraform does
+        #[arg(long)]
+        detailed_exitcode: bool,
+        #[command(flatten)]
+        backend: BackendArgs,
+        /// Also write the plan summary as Markdown, e.g. for a pull request comment
+        #[arg(long, value_name = "PATH")]
+        markdown: Option<PathBuf>,
+        #[command(flatten)]
+        matrix: MatrixArgs,
+        #[command(flatten)]
+        terraform: TerraformArgs,
+    },
+    /// Apply ./plan.plan, or plan and apply from the var file if no plan exists
+    Apply {
+        #[command(flatten)]
+        backend: BackendArgs,
+        #[command(flatten)]
+        terraform: TerraformArgs,
+    },
+    Destroy {
+        #[command(flatten)]
+        backend: BackendArgs,
+        #[command(flatten)]
+        terraform: TerraformArgs,
+    },
+    /// Run any terraform command, adding -var-file or -backend-config where it's accepted
+    Tf {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Show the current target, its resolved files and the state of .terraform and plan.plan
+    Status,
+    /// List every module with var files in the infra dir or a source directory in the repo
+    Ls,
+    /// Switch the current module to a saved profile
+    Use { profile: String },
+    /// Manage saved environment/region profiles
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+    /// Inspect and clean up the state remembered for each repo
+    State {
+        #[command(subcommand)]
+        command: StateCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProfileCommands {
+    /// Save the current environment and region under a name
+    Save { name: String },
+    List,
+    Rm { name: String },
+}
+
+#[derive(Subcommand)]
+pub enum StateCommands {
+    /// List every repo with a state file
+    List,
+    /// Show the current repo's state file
+    Show,
+    /// Remove the state of repos that no longer exist
+    Prune {
+        /// List what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Delete the current repo's state, so that its modules start over from the defaults
+    Reset,
+}
+
+fn parse_segment(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected NAME=VALUE, got {:?}", value))
 }
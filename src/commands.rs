@@ -0,0 +1,188 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use crate::backend::record_init;
+use crate::error::ModuleError;
+use crate::layout::Layout;
+use crate::output::{Event, Output};
+use crate::plan::{self, PlanSummary};
+use crate::repo::utf8;
+use crate::repo_config::RepoConfig;
+use crate::resolve::{get_module_var_dir, get_module_var_files, var_file_args};
+use crate::terraform::{build_args, injected_var_file, Runner, TerraformArgs};
+use crate::Config;
+
+pub fn init_args(
+    backend_config: &Path,
+    repo_config: &RepoConfig,
+    terraform: &TerraformArgs,
+) -> Result<Vec<String>, ModuleError> {
+    Ok(build_args(
+        &["init", "-backend-config", utf8(backend_config)?],
+        &repo_config.default_args("init", &["-get=true", "-force-copy", "-reconfigure"]),
+        terraform,
+    ))
+}
+
+/// `plan` with the var files, writing the plan to `plan_path`
+pub fn plan_args(
+    var_files: &[PathBuf],
+    plan_path: &str,
+    detailed_exitcode: bool,
+    repo_config: &RepoConfig,
+    terraform: &TerraformArgs,
+) -> Result<Vec<String>, ModuleError> {
+    let mut required = vec!["plan".to_string()];
+    required.extend(var_file_args(var_files)?);
+    required.push(format!("-out={}", plan_path));
+    if detailed_exitcode {
+        required.push("-detailed-exitcode".to_string());
+    }
+    Ok(build_args(
+        &required,
+        &repo_config.default_args("plan", &["-lock-timeout=30s"]),
+        terraform,
+    ))
+}
+
+/// Runs `terraform init` with the module's backend config, recording it in `data_dir` for
+/// `check_backend`.
+pub fn init(
+    runner: &dyn Runner,
+    out: &Output,
+    layout: &Layout,
+    repo_config: &RepoConfig,
+    config: &Config,
+    data_dir: &Path,
+    terraform: &TerraformArgs,
+) -> anyhow::Result<ExitStatus> {
+    let module_path = get_module_var_dir(layout, config, "backend")?;
+    let args = init_args(&module_path, repo_config, terraform)?;
+
+    let init_status = runner.run(&args, out)?;
+    record_init(data_dir, init_status, &module_path)?;
+    Ok(init_status)
+}
+
+/// Runs `terraform plan` with the module's var files, writing the plan to `plan_path`. When the
+/// plan succeeds, writes its manifest for `apply` and returns its summary, also written as
+/// Markdown to `markdown` if given.
+#[allow(clippy::too_many_arguments)]
+pub fn plan(
+    runner: &dyn Runner,
+    out: &Output,
+    layout: &Layout,
+    repo_config: &RepoConfig,
+    config: &Config,
+    plan_path: &str,
+    detailed_exitcode: bool,
+    markdown: Option<&Path>,
+    terraform: &TerraformArgs,
+) -> anyhow::Result<(ExitStatus, Option<PlanSummary>)> {
+    let var_files = get_module_var_files(layout, config)?;
+    out.event(Event::VarFiles {
+        var_files: &var_files,
+    });
+    let args = plan_args(&var_files, plan_path, detailed_exitcode, repo_config, terraform)?;
+    let plan_status = runner.run(&args, out)?;
+    // with -detailed-exitcode, 2 means the plan succeeded and has changes
+    if !(plan_status.success() || (detailed_exitcode && plan_status.code() == Some(2))) {
+        return Ok((plan_status, None));
+    }
+
+    plan::write_manifest(plan_path, config, &var_files)?;
+    // the plan itself succeeded, so its exit status is kept if it can't be summarized
+    let summary = match runner.show_json(plan_path) {
+        Ok(plan_json) => plan::summarize(&plan_json),
+        Err(err) => {
+            out.message(format!("Warning: could not summarize the plan: {:#}", err));
+            return Ok((plan_status, None));
+        }
+    };
+    out.event(Event::PlanSummary(&summary));
+    if let Some(markdown) = markdown {
+        fs::write(markdown, plan::to_markdown(&layout.describe(config), &summary))?;
+    }
+    Ok((plan_status, Some(summary)))
+}
+
+/// Applies `plan.plan` if it exists and matches the target, otherwise applies with the var files.
+pub fn apply(
+    runner: &dyn Runner,
+    out: &Output,
+    layout: &Layout,
+    repo_config: &RepoConfig,
+    config: &Config,
+    var_files: &[PathBuf],
+    terraform: &TerraformArgs,
+) -> anyhow::Result<ExitStatus> {
+    let defaults = repo_config.default_args("apply", &["-lock-timeout=30s"]);
+    let args = if Path::new(plan::PLAN_PATH).exists() {
+        plan::check_manifest(layout, config, var_files)?;
+        let mut args = build_args(&["apply"], &defaults, terraform);
+        args.push(plan::PLAN_PATH.to_string());
+        args
+    } else {
+        out.event(Event::VarFiles { var_files });
+        let mut required = vec!["apply".to_string()];
+        required.extend(var_file_args(var_files)?);
+        build_args(&required, &defaults, terraform)
+    };
+
+    runner.run(&args, out)
+}
+
+pub fn destroy(
+    runner: &dyn Runner,
+    out: &Output,
+    repo_config: &RepoConfig,
+    var_files: &[PathBuf],
+    terraform: &TerraformArgs,
+) -> anyhow::Result<ExitStatus> {
+    out.event(Event::VarFiles { var_files });
+    let mut required = vec!["destroy".to_string()];
+    required.extend(var_file_args(var_files)?);
+    let args = build_args(&required, &repo_config.default_args("destroy", &[]), terraform);
+    runner.run(&args, out)
+}
+
+/// Runs any terraform command, adding the module's var files or backend config where the
+/// subcommand accepts them. An init's backend config is recorded in `data_dir`, as `init` does.
+pub fn tf(
+    runner: &dyn Runner,
+    out: &Output,
+    layout: &Layout,
+    config: &Config,
+    data_dir: &Path,
+    args: &[String],
+) -> anyhow::Result<ExitStatus> {
+    let mut args = args.to_owned();
+    let mut backend_config = None;
+    if let Some((flag, basename)) = injected_var_file(&args[0]) {
+        let mut paths = if basename == "terraform" {
+            let var_files = get_module_var_files(layout, config)?;
+            out.event(Event::VarFiles {
+                var_files: &var_files,
+            });
+            var_files
+        } else {
+            vec![get_module_var_dir(layout, config, basename)?]
+        };
+        let mut injected = vec![];
+        for path in &paths {
+            injected.extend([flag.to_string(), utf8(path)?.to_string()]);
+        }
+        args.splice(1..1, injected);
+        if basename == "backend" {
+            backend_config = paths.pop();
+        }
+    }
+
+    let tf_status = runner.run(&args, out)?;
+    // keep `check_backend` in step with the data dir
+    if let Some(backend_config) = backend_config {
+        record_init(data_dir, tf_status, &backend_config)?;
+    }
+    Ok(tf_status)
+}
@@ -0,0 +1,51 @@
+use std::io::{self, IsTerminal};
+
+use dialoguer::{theme::ColorfulTheme, Input};
+
+use condeform::error::ModuleError;
+use condeform::layout::Layout;
+use condeform::output::{Event, Output};
+use condeform::repo_config::RepoConfig;
+use condeform::Config;
+
+pub const CONFIRM_FLAG_PREFIX: &str = "--yes-i-mean-";
+
+/// Shows a banner before running against a protected environment and, for `destructive`
+/// commands, requires the user to type the environment's name. Without a terminal, the
+/// command is refused unless `--yes-i-mean-<env>` was passed.
+pub fn confirm_target(
+    out: &Output,
+    layout: &Layout,
+    repo_config: &RepoConfig,
+    config: &Config,
+    confirmed: &[String],
+    destructive: bool,
+) -> anyhow::Result<()> {
+    let environment = match &config.environment {
+        Some(env) if repo_config.protected.contains(env) => env,
+        _ => return Ok(()),
+    };
+
+    out.event(Event::Protected {
+        target: layout.describe(config),
+    });
+
+    if !destructive || confirmed.contains(environment) {
+        return Ok(());
+    }
+
+    // the prompt is shown on stderr and read from stdin, so stdout can still be piped
+    if !io::stdin().is_terminal() || !console::user_attended_stderr() {
+        return Err(ModuleError::ConfirmationRequired(environment.to_owned()).into());
+    }
+
+    let input = Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Type {} to confirm", environment))
+        .allow_empty(true)
+        .interact_text()?;
+
+    if input != *environment {
+        return Err(ModuleError::NotConfirmed(environment.to_owned()).into());
+    }
+    Ok(())
+}
@@ -1,9 +1,93 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 
+/// Exit code for errors in condeform's own or the repo's configuration
+pub const EXIT_CONFIG: u8 = 3;
+/// Exit code when the repo, infra dir, var files or tools condeform needs can't be found
+pub const EXIT_ENVIRONMENT: u8 = 4;
+/// Exit code when the state file can't be read
+pub const EXIT_STATE: u8 = 5;
+/// Exit code when the plan or `.terraform` doesn't match the current target
+pub const EXIT_MISMATCH: u8 = 6;
+/// Exit code when a protected environment wasn't confirmed
+pub const EXIT_NOT_CONFIRMED: u8 = 7;
+
 #[derive(Error, Debug, Clone)]
 pub enum ModuleError {
-    #[error("Module not found for environment: {environment:?}, region: {region:?}")]
-    NotADirectory { environment: String, region: String },
-    #[error("Config value {0:?} must be set")]
-    IncompleteConfig(String)
+    #[error("Module not found at {0:?}. Check the target with `condeform status`, or change it with `condeform edit`")]
+    NotADirectory(String),
+    #[error("Config value {0:?} must be set. Set it with `condeform edit` or `--segment {0}=<value>`")]
+    IncompleteConfig(String),
+    #[error("Plan was created for {planned}, but the current target is {current}. Re-run `condeform plan`")]
+    StalePlan { planned: String, current: String },
+    #[error("No manifest found for {0:?}, cannot verify which target it was planned against. Re-run `condeform plan`")]
+    MissingPlanManifest(String),
+    #[error("Var file {0:?} has changed since the plan was created. Re-run `condeform plan`")]
+    ChangedVarFile(String),
+    #[error("Plan was created with var files [{planned}], but the current var files are [{current}]. Re-run `condeform plan`")]
+    ChangedVarFiles { planned: String, current: String },
+    #[error("No profile named {0:?}, see `condeform profile list`")]
+    UnknownProfile(String),
+    #[error("Invalid layout {layout:?}: {reason}. Fix layout in the repo's .condeform.toml, e.g. \"{{environment}}/{{region}}/{{module}}\"")]
+    InvalidLayout { layout: String, reason: String },
+    #[error("{name} {value:?} is not allowed by the repo's .condeform.toml, expected one of: {allowed}")]
+    NotAllowed { name: String, value: String, allowed: String },
+    #[error("{0:?} is a protected environment. Pass --yes-i-mean-{0} to confirm without a terminal")]
+    ConfirmationRequired(String),
+    #[error("Confirmation for protected environment {0:?} did not match, aborting")]
+    NotConfirmed(String),
+    #[error(".terraform was initialized with {initialized:?}, but the current backend config is {current:?}, or it has changed since. Run `condeform init` or pass --reinit")]
+    BackendChanged { initialized: String, current: String },
+    #[error("Could not read {0:?} with `terraform show -json`. Check the plan with `condeform status`, or re-run `condeform plan`")]
+    ShowFailed(String),
+    #[error("No targets match the matrix. Check that the module exists in the selected environments and regions")]
+    NoMatrixTargets,
+    #[error("Infra dir {0:?} does not exist. Set it with `condeform edit`, --infra-dir, or infra_dir in the repo's .condeform.toml")]
+    MissingInfraDir(String),
+    #[error("Var file {0:?} does not exist. Create it, or check the target with `condeform status`")]
+    MissingVarFile(String),
+    #[error("Could not find the repo for {0:?}: it is not in a git repository, and no directory above it has a .condeform.toml or .terraform. Run condeform from the infra repo, or add a .condeform.toml at its root")]
+    RepoNotFound(PathBuf),
+    #[error("State file {path:?} is corrupt: {reason}. Fix it, or delete it to start over with the repo's defaults")]
+    CorruptState { path: PathBuf, reason: String },
+    #[error("Repo config {path:?} is invalid: {reason}. Fix it using the settings in the README's \"Repo config\" section")]
+    InvalidRepoConfig { path: PathBuf, reason: String },
+    #[error("Could not find the state directory. Set $XDG_STATE_HOME or $HOME")]
+    NoStateDir,
+    #[error("Path {0:?} is not valid UTF-8, which terraform arguments and the state file need. Rename it, or run from a path that is")]
+    NonUtf8Path(PathBuf),
+    #[error("Terraform executable {0:?} was not found. Install terraform or OpenTofu, or choose the executable with --terraform-bin")]
+    TerraformNotFound(String),
+}
+
+impl ModuleError {
+    /// Condeform's exit code for the error, by category. Terraform's own failures exit with
+    /// terraform's exit code, and other errors with 1.
+    pub fn exit_code(&self) -> u8 {
+        use ModuleError::*;
+        match self {
+            IncompleteConfig(_)
+            | UnknownProfile(_)
+            | InvalidLayout { .. }
+            | NotAllowed { .. }
+            | NoMatrixTargets
+            | InvalidRepoConfig { .. } => EXIT_CONFIG,
+            NotADirectory(_)
+            | MissingInfraDir(_)
+            | MissingVarFile(_)
+            | RepoNotFound(_)
+            | NoStateDir
+            | NonUtf8Path(_)
+            | TerraformNotFound(_)
+            | ShowFailed(_) => EXIT_ENVIRONMENT,
+            CorruptState { .. } => EXIT_STATE,
+            StalePlan { .. }
+            | MissingPlanManifest(_)
+            | ChangedVarFile(_)
+            | ChangedVarFiles { .. }
+            | BackendChanged { .. } => EXIT_MISMATCH,
+            ConfirmationRequired(_) | NotConfirmed(_) => EXIT_NOT_CONFIRMED,
+        }
+    }
 }
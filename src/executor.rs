@@ -0,0 +1,139 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Process ids of the children that are still running
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Forwards Ctrl-C to the running children instead of exiting, so that terraform can stop
+/// cleanly and release its state locks. No new commands are started afterwards.
+pub fn forward_interrupts() -> anyhow::Result<()> {
+    ctrlc::set_handler(|| {
+        let running = RUNNING.lock().unwrap();
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        for pid in running.iter() {
+            interrupt(*pid);
+        }
+    })?;
+    Ok(())
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Calls `f` for each item on up to `jobs` threads, returning the results in the items' order.
+pub fn run_parallel<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter().flatten().collect()
+}
+
+/// Runs a command with each line of its output prefixed, or returns `None` without running it
+/// after an interrupt. With `stdout_to_stderr`, all of its output goes to stderr.
+pub fn run_prefixed(
+    command: &mut Command,
+    prefix: &str,
+    stdout_to_stderr: bool,
+) -> io::Result<Option<ExitStatus>> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    new_process_group(command);
+
+    // Holding the lock while spawning means the interrupt handler either stops this command
+    // from starting or sees it in `RUNNING`
+    let mut child = {
+        let mut running = RUNNING.lock().unwrap();
+        if interrupted() {
+            return Ok(None);
+        }
+        let child = command.spawn()?;
+        running.push(child.id());
+        child
+    };
+
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    thread::scope(|scope| {
+        if let Some(stdout) = stdout {
+            scope.spawn(|| copy_lines(stdout, prefix, stdout_to_stderr));
+        }
+        if let Some(stderr) = stderr {
+            scope.spawn(|| copy_lines(stderr, prefix, true));
+        }
+    });
+
+    // Reaping under the lock, and only then leaving `RUNNING`, means the interrupt handler can't
+    // signal a pid that has already been reused
+    loop {
+        let mut running = RUNNING.lock().unwrap();
+        let status = child.try_wait();
+        if !matches!(status, Ok(None)) {
+            running.retain(|pid| *pid != child.id());
+            return status;
+        }
+        drop(running);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn copy_lines(stream: impl Read, prefix: &str, to_stderr: bool) {
+    for line in BufReader::new(stream).split(b'\n').map_while(Result::ok) {
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\r');
+        if to_stderr {
+            eprintln!("{} {}", prefix, line);
+        } else {
+            println!("{} {}", prefix, line);
+        }
+    }
+}
+
+/// Keeps the terminal's Ctrl-C from reaching the children directly, so that they're only
+/// interrupted once, by `forward_interrupts`. Terraform exits immediately without cleaning up
+/// on a second interrupt.
+#[cfg(unix)]
+fn new_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn new_process_group(_command: &mut Command) {}
+
+#[cfg(unix)]
+fn interrupt(pid: u32) {
+    // SAFETY: kill has no memory safety requirements, and `pid` is in `RUNNING`, whose lock is
+    // held. `run_prefixed` reaps a child and removes it under the same lock, so the pid can't
+    // have been reused
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGINT);
+    }
+}
+
+#[cfg(not(unix))]
+fn interrupt(_pid: u32) {}
@@ -0,0 +1,146 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::layout::{Layout, Segment};
+use crate::matrix::{self, MatrixSpec};
+use crate::repo_config::RepoConfig;
+use crate::Config;
+
+/// Every module that has var files in the infra dir or a source directory in the repo
+#[derive(Serialize)]
+pub struct Inventory {
+    pub modules: Vec<ModuleInventory>,
+}
+
+#[derive(Serialize)]
+pub struct ModuleInventory {
+    pub name: String,
+    /// Directories with `.tf` files named after the module, relative to the repo root
+    pub sources: Vec<PathBuf>,
+    pub targets: Vec<InventoryTarget>,
+    /// Environments that have var files for other modules, but not for this one
+    pub missing_environments: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct InventoryTarget {
+    pub target: String,
+    pub var_files: Vec<String>,
+}
+
+/// Walks the infra dir along the layout for module directories with `.tfvars` files, and the
+/// repo for module sources, matching them up by the module's directory name.
+pub fn build(
+    layout: &Layout,
+    repo_config: &RepoConfig,
+    base: &Config,
+    repo_root: &Path,
+) -> anyhow::Result<Inventory> {
+    let spec: MatrixSpec = layout
+        .segments()
+        .iter()
+        .filter_map(|v| match v {
+            Segment::Named(name) => Some((name.to_string(), vec!["*".to_string()])),
+            Segment::Literal(_) => None,
+        })
+        .collect();
+
+    let mut modules: BTreeMap<String, (ModuleInventory, BTreeSet<String>)> = BTreeMap::new();
+    let mut environments = BTreeSet::new();
+    for target in matrix::targets(layout, repo_config, base, &spec)? {
+        let var_files = var_files_in(&layout.module_dir(&target)?)?;
+        if var_files.is_empty() {
+            continue;
+        }
+        let (module, module_environments) = entry(&mut modules, &target.module);
+        module.targets.push(InventoryTarget {
+            target: layout.describe(&target),
+            var_files,
+        });
+        if let Some(environment) = &target.environment {
+            environments.insert(environment.to_owned());
+            module_environments.insert(environment.to_owned());
+        }
+    }
+
+    let mut sources = vec![];
+    find_sources(repo_root, &mut sources)?;
+    for source in sources {
+        if let Some(name) = source.file_name().and_then(|v| v.to_str()) {
+            let (module, _) = entry(&mut modules, name);
+            module
+                .sources
+                .push(source.strip_prefix(repo_root).unwrap_or(&source).to_path_buf());
+        }
+    }
+
+    Ok(Inventory {
+        modules: modules
+            .into_values()
+            .map(|(mut module, module_environments)| {
+                module.missing_environments = environments
+                    .difference(&module_environments)
+                    .cloned()
+                    .collect();
+                module
+            })
+            .collect(),
+    })
+}
+
+fn entry<'a>(
+    modules: &'a mut BTreeMap<String, (ModuleInventory, BTreeSet<String>)>,
+    name: &str,
+) -> &'a mut (ModuleInventory, BTreeSet<String>) {
+    modules.entry(name.to_string()).or_insert_with(|| {
+        (
+            ModuleInventory {
+                name: name.to_string(),
+                sources: vec![],
+                targets: vec![],
+                missing_environments: vec![],
+            },
+            BTreeSet::new(),
+        )
+    })
+}
+
+fn var_files_in(dir: &Path) -> io::Result<Vec<String>> {
+    let mut var_files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|v| v == "tfvars") {
+            var_files.extend(path.file_name().and_then(|v| v.to_str()).map(String::from));
+        }
+    }
+    var_files.sort_unstable();
+    Ok(var_files)
+}
+
+/// Directories under `dir` with `.tf` files, skipping hidden directories like `.git` and
+/// `.terraform`, and not following symlinks.
+fn find_sources(dir: &Path, sources: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|v| v.file_name());
+
+    let mut has_tf = false;
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            find_sources(&path, sources)?;
+        } else if path.extension().is_some_and(|v| v == "tf") {
+            has_tf = true;
+        }
+    }
+    if has_tf {
+        sources.push(dir.to_path_buf());
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::ModuleError;
+use crate::Config;
+
+pub const DEFAULT_LAYOUT: &str = "{environment}/{region}/{module}";
+/// Shown in place of a segment that isn't set
+const NONE: &str = "<none>";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// A directory name that is the same for every target
+    Literal(String),
+    /// A directory name taken from the config value of the same name, e.g. `{region}`
+    Named(String),
+}
+
+/// The directory structure below the infra dir, e.g. `{account}/{environment}/{region}/{module}`.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    segments: Vec<Segment>,
+}
+
+impl Layout {
+    pub fn parse(template: &str) -> Result<Layout, ModuleError> {
+        let invalid = |reason: &str| ModuleError::InvalidLayout {
+            layout: template.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let segments: Vec<Segment> = template
+            .split('/')
+            .filter(|v| !v.is_empty())
+            .map(|v| match v.strip_prefix('{').and_then(|v| v.strip_suffix('}')) {
+                Some(name) => Segment::Named(name.to_string()),
+                None => Segment::Literal(v.to_string()),
+            })
+            .collect();
+
+        if segments.last() != Some(&Segment::Named("module".to_string())) {
+            return Err(invalid("the last segment must be {module}"));
+        }
+        if segments.iter().any(|v| match v {
+            Segment::Named(name) => name.is_empty() || name.contains(['{', '}']),
+            Segment::Literal(value) => value.contains(['{', '}']),
+        }) {
+            return Err(invalid("segments must be a plain directory name or a single {name}"));
+        }
+
+        Ok(Layout { segments })
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// The module's directory, and every directory above it down from the infra dir.
+    pub fn dirs(&self, config: &Config) -> Result<Vec<PathBuf>, ModuleError> {
+        self.dirs_with(config, |name| Err(ModuleError::IncompleteConfig(name.to_string())))
+    }
+
+    /// Like `dirs`, but with `<none>` for the segments that aren't set, to show where an
+    /// incomplete target's files would be.
+    pub fn dirs_or_none(&self, config: &Config) -> Vec<PathBuf> {
+        self.dirs_with(config, |_| Ok(NONE)).unwrap_or_default()
+    }
+
+    fn dirs_with(
+        &self,
+        config: &Config,
+        unset: impl Fn(&str) -> Result<&'static str, ModuleError>,
+    ) -> Result<Vec<PathBuf>, ModuleError> {
+        let mut dir = Path::new(&config.infra_dir).to_path_buf();
+        let mut dirs = vec![dir.to_owned()];
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(value) => dir.push(value),
+                Segment::Named(name) => match config.segment(name) {
+                    Some(value) => dir.push(value),
+                    None => dir.push(unset(name)?),
+                },
+            }
+            dirs.push(dir.to_owned());
+        }
+        Ok(dirs)
+    }
+
+    pub fn module_dir(&self, config: &Config) -> Result<PathBuf, ModuleError> {
+        Ok(self.dirs(config)?.pop().unwrap())
+    }
+
+    /// The config's segment values joined by `/`, e.g. `prod/us-east-1/vpc`
+    pub fn describe(&self, config: &Config) -> String {
+        self.segments
+            .iter()
+            .filter_map(|v| match v {
+                Segment::Named(name) => Some(config.segment(name).unwrap_or(NONE)),
+                Segment::Literal(_) => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
@@ -0,0 +1,86 @@
+//! Resolves the var files, backend config and terraform executable for a module in an
+//! infra repo, and runs terraform with them. The `condeform` binary is a clap front end to this.
+//!
+//! ```no_run
+//! use condeform::resolve::get_module_var_files;
+//! use condeform::{repo, repo_config, state};
+//!
+//! let repo = repo::Repo::find(&std::env::current_dir()?, None)?;
+//! let repo_config = repo_config::read_repo_config(&repo.root)?;
+//! let layout = repo_config.layout()?;
+//!
+//! let state = state::read_state(&state::repo_state_path(&state::state_dir()?, &repo)?)?;
+//! let config = state.config_for("infra/terraform/vpc", "vpc", &repo_config.defaults(&repo.root));
+//! for var_file in get_module_var_files(&layout, &config)? {
+//!     println!("{}", var_file.display());
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+pub mod backend;
+pub mod commands;
+pub mod error;
+pub mod executor;
+pub mod inventory;
+pub mod layout;
+pub mod matrix;
+pub mod output;
+pub mod plan;
+pub mod repo;
+pub mod repo_config;
+pub mod resolve;
+pub mod state;
+pub mod status;
+pub mod terraform;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Config {
+    pub environment: Option<String>,
+    pub region: String,
+    pub module: String,
+    pub infra_dir: String,
+    /// Terraform executable for this module, e.g. `tofu`
+    pub terraform_bin: Option<String>,
+    /// Values for layout segments other than environment, region and module
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub segments: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn segment(&self, name: &str) -> Option<&str> {
+        match name {
+            "environment" => self.environment.as_deref(),
+            "region" => Some(&self.region),
+            "module" => Some(&self.module),
+            _ => self.segments.get(name).map(|v| v.as_str()),
+        }
+    }
+
+    pub fn set_segment(&mut self, name: &str, value: String) {
+        match name {
+            "environment" => self.environment = Some(value),
+            "region" => self.region = value,
+            "module" => self.module = value,
+            _ => {
+                self.segments.insert(name.to_string(), value);
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            environment: None,
+            region: "us-east-1".to_string(),
+            module: "vpc".to_string(),
+            infra_dir: "../../".to_string(),
+            terraform_bin: None,
+            segments: BTreeMap::new(),
+        }
+    }
+}
@@ -1,293 +1,308 @@
-use anyhow;
-use std::collections::HashSet;
-use std::env::current_dir;
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::process::Command;
-
-use clap::Parser;
-use dialoguer::{theme::ColorfulTheme, Input, Select};
-use etcetera::app_strategy::{AppStrategy, AppStrategyArgs, Xdg};
-use serde::{Deserialize, Serialize};
-
-const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
-const APP_NAME: &str = env!("CARGO_PKG_NAME");
-
 mod cli;
-mod error;
+mod confirm;
+mod prompt;
 
-use error::ModuleError;
+use std::env::current_dir;
+use std::path::Path;
+use std::process::ExitCode;
+
+use condeform::error::{ModuleError, EXIT_CONFIG};
+use condeform::output::{Event, Output};
+use condeform::repo::{get_module_key, utf8, Repo};
+use condeform::resolve::{get_module_var_dir, get_module_var_files};
+use condeform::terraform::{self, TerraformRunner};
+use condeform::{backend, commands, inventory, matrix, plan, repo_config, state, Config};
+
+use crate::cli::StateCommands;
+use crate::prompt::get_config_with_input;
+
+fn main() -> ExitCode {
+    let cli = match cli::Cli::parse_with_confirmations() {
+        Ok(cli) => cli,
+        // clap exits with 2 for usage errors, which `plan --detailed-exitcode` uses for changes
+        Err(err) => {
+            let _ = err.print();
+            return if err.use_stderr() {
+                ExitCode::from(EXIT_CONFIG)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    let out = Output {
+        format: cli.output,
+    };
 
-#[derive(Deserialize, Serialize)]
-struct Config {
-    environment: Option<String>,
-    region: String,
-    module: String,
-    infra_dir: String,
+    run(&cli, &out).unwrap_or_else(|err| {
+        out.event(Event::Error {
+            message: format!("{:#}", err),
+        });
+        err.downcast_ref::<ModuleError>()
+            .map_or(ExitCode::FAILURE, |err| ExitCode::from(err.exit_code()))
+    })
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            environment: None,
-            region: "us-east-1".to_string(),
-            module: "vpc".to_string(),
-            infra_dir: "../../".to_string(),
-        }
+fn run(cli: &cli::Cli, out: &Output) -> anyhow::Result<ExitCode> {
+    let cur_dir = current_dir()?;
+    let state_dir = state::state_dir()?;
+    if let cli::Commands::State { command } = &cli.command {
+        return run_state(cli, command, &cur_dir, &state_dir, out);
     }
-}
 
-fn main() -> Result<(), anyhow::Error> {
-    let strategy = Xdg::new(AppStrategyArgs {
-        top_level_domain: "org".to_string(),
-        author: AUTHORS.to_string(),
-        app_name: APP_NAME.to_string(),
-    })
-    .unwrap();
+    let repo = Repo::find(&cur_dir, cli.overrides.state_key.as_deref())?;
+    let state_path = state::repo_state_path(&state_dir, &repo)?;
+
+    let repo_config = repo_config::read_repo_config(&repo.root)?;
+    let layout = repo_config.layout()?;
+
+    let module_key = get_module_key(&repo.root, &cur_dir);
+    let mut repo_state = state::read_state(&state_path)?;
+    let state = repo_state.config_for(
+        &module_key,
+        utf8(Path::new(cur_dir.file_name().unwrap_or_default()))?,
+        &repo_config.defaults(&repo.root),
+    );
+    if !repo_state.modules.contains_key(&module_key) {
+        repo_state.set(&module_key, &state);
+        state::write_state(&state_path, &repo_state)?;
+    }
 
-    let state_dir = strategy.state_dir().unwrap();
+    let state = cli.overrides.apply(state);
+    if cli.overrides.save {
+        repo_config.check_allowed(&state)?;
+        repo_state.set(&module_key, &state);
+        state::write_state(&state_path, &repo_state)?;
+    }
 
-    fs::create_dir_all(&state_dir).expect("Could not create state directory");
-    let state_path = get_repo_state_filepath(&state_dir);
+    let tf = TerraformRunner::detect(&state, &repo_state, &cur_dir);
+    let data_dir = backend::data_dir();
 
-    let previous_state = fs::read_to_string(&state_path);
-    let cur_dir = current_dir().unwrap();
-    let state = match previous_state {
-        Ok(str) => toml::from_str(&str).unwrap(),
-        Err(_) => {
-            let default_state = Config {
-                module: cur_dir.file_name().unwrap().to_str().unwrap().to_string(),
-                ..Config::default()
-            };
-            write_state(&state_path, &default_state)?;
-            default_state
-        }
+    use cli::Commands::*;
+    use cli::ProfileCommands;
+    // an interactive init checks the target it prompts for instead, so that a saved target
+    // that's no longer allowed can be fixed with it
+    let prompts = matches!(cli.command, Init { interactive: Some(true), .. });
+    let targets = !matches!(cli.command, Edit | Status | Ls | Use { .. } | Profile { .. } | State { .. });
+    if targets && !prompts {
+        repo_config.check_allowed(&state)?;
+        out.event(Event::Config {
+            target: layout.describe(&state),
+            config: &state,
+        });
+    }
+    // plan, apply and destroy run with the module's var files, against a data dir initialized
+    // with its backend config. A matrix run has a data dir per target instead
+    let mut var_files = vec![];
+    let backend = match &cli.command {
+        Plan {
+            backend, matrix, ..
+        } if !matrix.is_set() => Some((backend, false)),
+        Apply { backend, .. } | Destroy { backend, .. } => Some((backend, true)),
+        _ => None,
     };
+    if let Some((backend, destructive)) = backend {
+        var_files = get_module_var_files(&layout, &state)?;
+        confirm::confirm_target(out, &layout, &repo_config, &state, &cli.confirmed, destructive)?;
+        let init_status = backend::check_backend(
+            &tf,
+            out,
+            &layout,
+            &repo_config,
+            &state,
+            &data_dir,
+            backend.reinit,
+        )?;
+        if let Some(init_status) = init_status {
+            return Ok(terraform::exit_code(init_status));
+        }
+    }
 
-    let cli = cli::Cli::parse();
-
-    use cli::Commands::*;
+    let mut status = None;
     match &cli.command {
-        Init { interactive } => {
+        Init {
+            interactive,
+            terraform,
+        } => {
             let config = {
                 if let Some(true) = interactive {
-                    let state = get_config_with_input(&state, &cur_dir)?;
-                    write_state(&state_path, &state)?;
+                    let state = get_config_with_input(&state, &cur_dir, &layout, &repo_config)?;
+                    repo_config.check_allowed(&state)?;
+                    out.event(Event::Config {
+                        target: layout.describe(&state),
+                        config: &state,
+                    });
+                    repo_state.set(&module_key, &state);
+                    state::write_state(&state_path, &repo_state)?;
                     state
                 } else {
                     state
                 }
             };
 
-            let module_path = get_module_var_dir(&config, "backend")?;
-
-            let args = vec![
-                "init",
-                "-get=true",
-                "-force-copy",
-                "-backend-config",
-                module_path.to_str().unwrap(),
-                "-reconfigure",
-            ];
-
-            println!("terraform {}", args.join(" "));
+            get_module_var_dir(&layout, &config, "backend")?;
+            confirm::confirm_target(out, &layout, &repo_config, &config, &cli.confirmed, false)?;
 
-            Command::new("terraform").args(args).status()?;
+            status = Some(commands::init(&tf, out, &layout, &repo_config, &config, &data_dir, terraform)?);
         }
         Edit => {
-            let new_state = get_config_with_input(&state, &cur_dir)?;
-            write_state(&state_path, &new_state)?;
+            let new_state = get_config_with_input(&state, &cur_dir, &layout, &repo_config)?;
+            repo_config.check_allowed(&new_state)?;
+            repo_state.set(&module_key, &new_state);
+            state::write_state(&state_path, &repo_state)?;
         }
-        Plan => {
-            let module_path = get_module_var_dir(&state, "terraform")?;
-            let args = vec![
-                "plan",
-                "-var-file",
-                module_path.to_str().unwrap(),
-                "-out=./plan.plan",
-                "-lock-timeout=30s",
-            ];
-
-            println!("terraform {}", args.join(" "));
+        Plan {
+            detailed_exitcode,
+            markdown,
+            matrix,
+            terraform,
+            ..
+        } => {
+            if matrix.is_set() {
+                let spec = matrix::spec(matrix.all_envs, matrix.all_regions, &matrix.matrix);
+                let targets = matrix::targets(&layout, &repo_config, &state, &spec)?;
+                for target in &targets {
+                    confirm::confirm_target(out, &layout, &repo_config, target, &cli.confirmed, false)?;
+                }
+                let results = matrix::plan_matrix(
+                    &tf,
+                    out,
+                    &layout,
+                    &repo_config,
+                    &cur_dir,
+                    &targets,
+                    matrix.jobs,
+                    terraform,
+                )?;
+                return Ok(matrix::exit_code(&results, *detailed_exitcode));
+            }
 
-            Command::new("terraform").args(args).status()?;
+            let (plan_status, _) = commands::plan(
+                &tf,
+                out,
+                &layout,
+                &repo_config,
+                &state,
+                plan::PLAN_PATH,
+                *detailed_exitcode,
+                markdown.as_deref(),
+                terraform,
+            )?;
+            status = Some(plan_status);
         }
-        Destroy => {
-            let module_path = get_module_var_dir(&state, "terraform")?;
-            let args = vec!["destroy", "-var-file", module_path.to_str().unwrap()];
-
-            println!("terraform {}", args.join(" "));
-
-            Command::new("terraform").args(args).status()?;
+        Apply { terraform, .. } => {
+            status = Some(commands::apply(
+                &tf,
+                out,
+                &layout,
+                &repo_config,
+                &state,
+                &var_files,
+                terraform,
+            )?);
         }
+        Destroy { terraform, .. } => {
+            status = Some(commands::destroy(&tf, out, &repo_config, &var_files, terraform)?);
+        }
+        Tf { args } => {
+            let destructive = !terraform::read_only(args);
+            confirm::confirm_target(out, &layout, &repo_config, &state, &cli.confirmed, destructive)?;
+            status = Some(commands::tf(&tf, out, &layout, &state, &data_dir, args)?);
+        }
+        Status => out.event(Event::Status(&condeform::status::get_status(&layout, &state, &state_path, &data_dir)?)),
+        Ls => out.event(Event::Inventory(&inventory::build(
+            &layout,
+            &repo_config,
+            &state,
+            &repo.root,
+        )?)),
+        Use { profile } => {
+            let profile = repo_state.profile(profile)?;
+            let new_state = Config {
+                environment: Some(profile.environment.to_owned()),
+                region: profile.region.to_owned(),
+                ..state
+            };
+            repo_config.check_allowed(&new_state)?;
+            get_module_var_dir(&layout, &new_state, "terraform")?;
+            repo_state.set(&module_key, &new_state);
+            state::write_state(&state_path, &repo_state)?;
+            out.message(format!("Using {}", layout.describe(&new_state)));
+        }
+        Profile { command } => match command {
+            ProfileCommands::Save { name } => {
+                let environment = state
+                    .environment
+                    .to_owned()
+                    .ok_or_else(|| ModuleError::IncompleteConfig("environment".to_string()))?;
+                let profile = state::Profile {
+                    environment,
+                    region: state.region.to_owned(),
+                };
+                repo_state.profiles.insert(name.to_owned(), profile);
+                state::write_state(&state_path, &repo_state)?;
+            }
+            ProfileCommands::List => {
+                let current = repo_state.profiles.iter().find(|(_, profile)| {
+                    Some(&profile.environment) == state.environment.as_ref()
+                        && profile.region == state.region
+                });
+                out.event(Event::Profiles {
+                    profiles: &repo_state.profiles,
+                    current: current.map(|(name, _)| name.as_str()),
+                });
+            }
+            ProfileCommands::Rm { name } => {
+                repo_state.profile(name)?;
+                repo_state.profiles.remove(name);
+                state::write_state(&state_path, &repo_state)?;
+            }
+        },
+        State { .. } => unreachable!("handled by run_state"),
     };
-    Ok(())
+    Ok(status.map_or(ExitCode::SUCCESS, terraform::exit_code))
 }
 
-fn env_input(
-    infra_dir: &String,
-    config: &Config,
-    theme: &ColorfulTheme,
-) -> anyhow::Result<String> {
-    let infra_path = Path::new(infra_dir).to_path_buf();
-
-    let mut uniq = HashSet::new();
-
-    let mut items: Vec<String> = get_dirnames_from_path(&infra_path)
-        .filter(|v| v != "terraform")
-        .collect();
-
-    items.sort_unstable();
-
-    if let Some(env) = &config.environment {
-        items.insert(0, env.to_owned());
-    }
-
-    items.retain(|v| uniq.insert(v.to_owned()));
-
-    let env_index = Select::with_theme(theme)
-        .with_prompt("Environment")
-        .items(&items)
-        .default(0)
-        .interact_opt()
-        .expect("Cannot process input");
-
-    if let Some(idx) = env_index {
-        Ok(items[idx].to_owned())
-    } else {
-        Err(ModuleError::IncompleteConfig("environment".to_string()).into())
-    }
-}
-
-fn get_dirnames_from_path(path: &PathBuf) -> impl Iterator<Item=String> {
-    path.read_dir()
-        .unwrap()
-        .filter_map(|v| v.ok())
-        .map(|v| v.path())
-        .filter(|v| v.is_dir())
-        .filter_map(|v| {
-            if let Some(filename) = v.file_name() {
-                filename.to_str().and_then(|c| Some(c.to_string()))
+/// Runs before the module's state is read, so that the state can be listed and pruned from
+/// anywhere, and reset when it's corrupt.
+fn run_state(
+    cli: &cli::Cli,
+    command: &StateCommands,
+    cur_dir: &Path,
+    state_dir: &Path,
+    out: &Output,
+) -> anyhow::Result<ExitCode> {
+    match command {
+        StateCommands::List => out.event(Event::StateFiles {
+            state_files: &state::list(state_dir)?,
+        }),
+        StateCommands::Prune { dry_run } => {
+            let pruned = state::prune(state_dir, *dry_run)?;
+            for state_file in &pruned {
+                out.message(format!(
+                    "{} {} ({})",
+                    if *dry_run { "Would remove" } else { "Removed" },
+                    state_file.path.display(),
+                    state_file.state_key
+                ));
+            }
+            if pruned.is_empty() {
+                out.message("Every repo with a state file still exists");
+            }
+        }
+        StateCommands::Show | StateCommands::Reset => {
+            let repo = Repo::find(cur_dir, cli.overrides.state_key.as_deref())?;
+            let state_path = state::repo_state_path(state_dir, &repo)?;
+            if let StateCommands::Show = command {
+                out.event(Event::RepoState {
+                    state_file: &state_path,
+                    state: &state::read_state(&state_path)?,
+                });
             } else {
-                None
+                state::reset(state_dir, &state_path)?;
+                out.message(format!("Reset the state of {}", repo.state_key));
             }
-        })
-}
-
-fn region_input(config: &Config, infra_path: &PathBuf, env: &String, theme: &ColorfulTheme) -> String {
-
-    let mut env_path = PathBuf::new();
-    env_path = env_path.join(infra_path);
-    env_path.push(env);
-
-    let mut items: Vec<String> = get_dirnames_from_path(&env_path)
-        .collect();
-
-
-    let mut uniq = HashSet::new();
-    items.sort_unstable();
-
-    items.insert(0, config.region.to_owned());
-    items.retain(|v| uniq.insert(v.to_owned()));
-    let region_index = Select::with_theme(theme)
-        .with_prompt("Select region or <ESC> for text input")
-        .items(&items)
-        .default(0)
-        .interact_opt()
-        .expect("Exited");
-
-    match region_index {
-        Some(idx) => items[idx].to_owned(),
-        None => {
-            let default_region = items[0].to_owned();
-            Input::<String>::with_theme(theme)
-                .with_prompt("Region")
-                .default(default_region)
-                .interact_text()
-                .expect("Cannot process input")
         }
     }
+    Ok(ExitCode::SUCCESS)
 }
 
-fn get_git_root() -> PathBuf {
-    let repo_root = Command::new("git")
-        .args(vec!["rev-parse", "--show-toplevel"])
-        .output()
-        .expect("Could not determine git repo");
-    let mut git_path: String = String::from_utf8(repo_root.stdout).unwrap();
-    git_path = git_path
-        .strip_suffix("\n")
-        .map_or(git_path.to_owned(), |v| v.to_string());
-
-    let mut path = PathBuf::new();
-    path.push(&git_path);
-    path
-}
-
-fn get_repo_state_filepath(state_dir: &PathBuf) -> PathBuf {
-    let git_root = get_git_root();
-
-    let filename = git_root.to_str().unwrap().to_string().replace("/", "%");
-
-    let mut state_filepath = Path::new(&state_dir).to_path_buf();
-    state_filepath.push(filename);
-    state_filepath.set_extension("toml");
-    state_filepath
-}
-
-fn get_module_var_dir(config: &Config, basename: &str) -> Result<PathBuf, ModuleError> {
-    let mut module_path = PathBuf::new();
-    module_path.push(&config.infra_dir);
-    if let Some(env) = &config.environment {
-        module_path.push(env);
-    }
-    module_path.push(&config.region);
-    module_path.push(&config.module);
-
-    if let false = module_path.is_dir() {
-        return Err(ModuleError::NotADirectory {
-            environment: config.environment.as_ref().unwrap().to_owned(),
-            region: config.region.to_owned(),
-        });
-    }
-
-    module_path.push(basename);
-    module_path.set_extension("tfvars");
-    Ok(module_path)
-}
-
-fn get_config_with_input(state: &Config, cwd: &PathBuf) -> anyhow::Result<Config> {
-    let theme = ColorfulTheme::default();
-
-    let infra_dir = Input::<String>::with_theme(&theme)
-        .with_prompt("Infra Dir")
-        .default(state.infra_dir.to_string())
-        .interact_text()
-        .expect("Cannot process input");
-
-    let infra_path = cwd.join(&infra_dir).canonicalize().unwrap();
-
-    let environment = env_input(&infra_dir, state, &theme)?;
-    let region = region_input(&state, &infra_path, &environment, &theme);
-    let module = Input::<String>::with_theme(&theme)
-        .with_prompt("Module")
-        .with_initial_text(current_dir().map_or(state.module.to_string(), |v| {
-            v.file_name().unwrap().to_str().unwrap().to_string()
-        }))
-        .default(state.module.to_string())
-        .interact_text()
-        .expect("Cannot process input");
-
-
-    Ok(Config {
-        environment: Some(environment),
-        region,
-        module,
-        infra_dir: infra_path.to_str().unwrap().to_string(),
-    })
-}
-
-fn write_state(state_path: &PathBuf, config: &Config) -> anyhow::Result<()> {
-    fs::write(state_path, toml::to_string(config).unwrap())?;
-    Ok(())
-}
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use serde::Serialize;
+
+use crate::commands;
+use crate::error::ModuleError;
+use crate::executor;
+use crate::layout::{Layout, Segment};
+use crate::output::{Event, Output};
+use crate::repo_config::RepoConfig;
+use crate::resolve::{get_module_var_dir, get_module_var_files};
+use crate::terraform::{Runner, TerraformArgs};
+use crate::repo::get_dirnames_from_path;
+use crate::Config;
+
+/// Holds a data dir and plan file for each target of a matrix run, inside the module dir
+pub const MATRIX_DIR: &str = ".condeform";
+
+/// Segment values to run for, keyed by segment name. `*` means every directory found.
+pub type MatrixSpec = BTreeMap<String, Vec<String>>;
+
+#[derive(Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum TargetResult {
+    NoChanges,
+    Changes,
+    Failed { step: String },
+    Interrupted,
+}
+
+#[derive(Serialize)]
+pub struct MatrixResult {
+    pub target: String,
+    #[serde(flatten)]
+    pub result: TargetResult,
+}
+
+/// Builds the spec from `--all-envs`, `--all-regions` and `--matrix NAME=VALUE,...`.
+pub fn spec(all_envs: bool, all_regions: bool, matrix: &[(String, String)]) -> MatrixSpec {
+    let mut spec = MatrixSpec::new();
+    if all_envs {
+        spec.insert("environment".to_string(), vec!["*".to_string()]);
+    }
+    if all_regions {
+        spec.insert("region".to_string(), vec!["*".to_string()]);
+    }
+    for (name, values) in matrix {
+        let name = if name == "env" { "environment" } else { name };
+        spec.insert(
+            name.to_string(),
+            values.split(',').map(|v| v.to_string()).collect(),
+        );
+    }
+    spec
+}
+
+/// Every combination of the spec's segment values, taking segments that aren't in the spec
+/// from `base`. Combinations whose module directory doesn't exist are skipped.
+pub fn targets(
+    layout: &Layout,
+    repo_config: &RepoConfig,
+    base: &Config,
+    spec: &MatrixSpec,
+) -> anyhow::Result<Vec<Config>> {
+    if !Path::new(&base.infra_dir).is_dir() {
+        return Err(ModuleError::MissingInfraDir(base.infra_dir.to_owned()).into());
+    }
+
+    let mut partial = vec![(base.clone(), PathBuf::from(&base.infra_dir))];
+    for segment in layout.segments() {
+        let mut next = vec![];
+        for (config, dir) in partial {
+            let name = match segment {
+                Segment::Literal(value) => {
+                    next.push((config, dir.join(value)));
+                    continue;
+                }
+                Segment::Named(name) => name,
+            };
+
+            let values = match spec.get(name) {
+                Some(values) if values == &["*"] => all_values(&dir, name, repo_config)?,
+                Some(values) => values.to_owned(),
+                None => config.segment(name).map(|v| v.to_string()).into_iter().collect(),
+            };
+            for value in values {
+                let mut config = config.clone();
+                config.set_segment(name, value.to_owned());
+                next.push((config, dir.join(value)));
+            }
+        }
+        partial = next;
+    }
+
+    Ok(partial
+        .into_iter()
+        .filter(|(_, dir)| dir.is_dir())
+        .map(|(config, _)| config)
+        .collect())
+}
+
+fn all_values(dir: &Path, name: &str, repo_config: &RepoConfig) -> io::Result<Vec<String>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut values: Vec<String> = get_dirnames_from_path(dir)?
+        .into_iter()
+        .filter(|v| !repo_config.exclude.contains(v))
+        .filter(|v| repo_config.allowed(name).is_none_or(|allowed| allowed.contains(v)))
+        .collect();
+    values.sort_unstable();
+    Ok(values)
+}
+
+/// The directory holding a target's data dir and plan file, e.g. `.condeform/prod_us-east-1_vpc`
+pub fn target_dir(layout: &Layout, config: &Config) -> PathBuf {
+    Path::new(MATRIX_DIR).join(layout.describe(config).replace('/', "_"))
+}
+
+/// 130 after Ctrl-C, 1 if any target failed, or with `detailed_exitcode`, 2 if any target has
+/// changes
+pub fn exit_code(results: &[MatrixResult], detailed_exitcode: bool) -> ExitCode {
+    let failed = results
+        .iter()
+        .any(|v| matches!(v.result, TargetResult::Failed { .. }));
+    let changed = results
+        .iter()
+        .any(|v| matches!(v.result, TargetResult::Changes));
+    if executor::interrupted() {
+        ExitCode::from(130)
+    } else if failed {
+        ExitCode::FAILURE
+    } else if detailed_exitcode && changed {
+        ExitCode::from(2)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Runs init for each target, then plan, `jobs` at a time, each with its own data dir and plan
+/// file under `work_dir`, then reports the results.
+#[allow(clippy::too_many_arguments)]
+pub fn plan_matrix(
+    runner: &dyn Runner,
+    out: &Output,
+    layout: &Layout,
+    repo_config: &RepoConfig,
+    work_dir: &Path,
+    targets: &[Config],
+    jobs: usize,
+    terraform: &TerraformArgs,
+) -> anyhow::Result<Vec<MatrixResult>> {
+    if targets.is_empty() {
+        return Err(ModuleError::NoMatrixTargets.into());
+    }
+
+    let mut commands = vec![];
+    for target in targets {
+        repo_config.check_allowed(target)?;
+        out.event(Event::Config {
+            target: layout.describe(target),
+            config: target,
+        });
+        commands.push(target_commands(out, layout, repo_config, work_dir, target, terraform)?);
+    }
+
+    executor::forward_interrupts()?;
+    // init writes `.terraform.lock.hcl` to the module dir, which every target shares, so the
+    // targets are initialized one at a time, and only planned in parallel
+    let mut results = vec![];
+    let mut initialized = vec![];
+    for (index, (target, data_dir, commands)) in commands.iter().enumerate() {
+        let result = match commands {
+            Some((init, plan)) => match init_target(runner, out, target, data_dir, init)? {
+                Some(failed) => failed,
+                None => {
+                    initialized.push((index, target, data_dir, plan));
+                    // replaced by the plan's result
+                    TargetResult::Interrupted
+                }
+            },
+            None => TargetResult::Failed {
+                step: "resolve".to_string(),
+            },
+        };
+        results.push(MatrixResult {
+            target: target.to_owned(),
+            result,
+        });
+    }
+
+    let planned = executor::run_parallel(&initialized, jobs, |(_, target, data_dir, plan)| {
+        plan_target(runner, out, target, data_dir, plan)
+    });
+    for ((index, ..), result) in initialized.iter().zip(planned) {
+        results[*index].result = result?;
+    }
+    out.event(Event::Matrix { results: &results });
+    Ok(results)
+}
+
+/// A target's name and data dir, and its init and plan arguments, which are `None` when its
+/// var files can't be found.
+type TargetCommands = (String, PathBuf, Option<(Vec<String>, Vec<String>)>);
+
+fn target_commands(
+    out: &Output,
+    layout: &Layout,
+    repo_config: &RepoConfig,
+    work_dir: &Path,
+    target: &Config,
+    terraform: &TerraformArgs,
+) -> anyhow::Result<TargetCommands> {
+    let name = layout.describe(target);
+    let target_dir = target_dir(layout, target);
+    let data_dir = target_dir.join("data");
+
+    let (backend_config, var_files) = match (
+        get_module_var_dir(layout, target, "backend"),
+        get_module_var_files(layout, target),
+    ) {
+        (Ok(backend_config), Ok(var_files)) => (backend_config, var_files),
+        _ => return Ok((name, data_dir, None)),
+    };
+    fs::create_dir_all(work_dir.join(&target_dir))?;
+    out.event(Event::VarFiles {
+        var_files: &var_files,
+    });
+
+    let init = commands::init_args(&backend_config, repo_config, &TerraformArgs::default())?;
+    let plan = commands::plan_args(
+        &var_files,
+        &target_dir.join("plan.plan").display().to_string(),
+        true,
+        repo_config,
+        terraform,
+    )?;
+
+    Ok((name, data_dir, Some((init, plan))))
+}
+
+/// Runs init in the target's data dir, returning the target's result if it failed.
+fn init_target(
+    runner: &dyn Runner,
+    out: &Output,
+    target: &str,
+    data_dir: &Path,
+    init: &[String],
+) -> anyhow::Result<Option<TargetResult>> {
+    Ok(match runner.run_target(init, &[("TF_DATA_DIR", data_dir)], target, out)? {
+        Some(status) if status.success() => None,
+        _ => Some(failed("init")),
+    })
+}
+
+fn plan_target(
+    runner: &dyn Runner,
+    out: &Output,
+    target: &str,
+    data_dir: &Path,
+    plan: &[String],
+) -> anyhow::Result<TargetResult> {
+    let status = runner.run_target(plan, &[("TF_DATA_DIR", data_dir)], target, out)?;
+    Ok(match status.map(|v| v.code()) {
+        Some(Some(0)) => TargetResult::NoChanges,
+        Some(Some(2)) => TargetResult::Changes,
+        _ => failed("plan"),
+    })
+}
+
+/// A step that didn't succeed, which is because of Ctrl-C if there was one
+fn failed(step: &str) -> TargetResult {
+    if executor::interrupted() {
+        TargetResult::Interrupted
+    } else {
+        TargetResult::Failed {
+            step: step.to_string(),
+        }
+    }
+}
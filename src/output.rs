@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use console::{style, Style};
+use serde::Serialize;
+
+use crate::inventory::Inventory;
+use crate::matrix::{MatrixResult, TargetResult};
+use crate::plan::PlanSummary;
+use crate::state::{Profile, State, StateFile};
+use crate::status::{FileStatus, InitStatus, PlanStatus, Status};
+use crate::Config;
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Human readable output
+    #[default]
+    Text,
+    /// One JSON event per line. Terraform's own output goes to stderr
+    Json,
+}
+
+/// Something condeform reports while running a command.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Config {
+        target: String,
+        config: &'a Config,
+    },
+    VarFiles {
+        var_files: &'a [PathBuf],
+    },
+    Protected {
+        target: String,
+    },
+    Exec {
+        argv: Vec<String>,
+        version: Option<String>,
+        source: &'a str,
+    },
+    Exit {
+        success: bool,
+        code: Option<i32>,
+        signal: Option<i32>,
+        duration_ms: u128,
+    },
+    PlanSummary(&'a PlanSummary),
+    Status(&'a Status),
+    Inventory(&'a Inventory),
+    Profiles {
+        profiles: &'a BTreeMap<String, Profile>,
+        current: Option<&'a str>,
+    },
+    Matrix {
+        results: &'a [MatrixResult],
+    },
+    StateFiles {
+        state_files: &'a [StateFile],
+    },
+    RepoState {
+        state_file: &'a Path,
+        state: &'a State,
+    },
+    Message {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Clone, Copy)]
+pub struct Output {
+    pub format: OutputFormat,
+}
+
+impl Output {
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    pub fn event(&self, event: Event) {
+        if self.is_json() {
+            match serde_json::to_string(&event) {
+                Ok(json) => println!("{}", json),
+                Err(err) => eprintln!("Error: could not write the event as JSON: {}", err),
+            }
+            return;
+        }
+
+        match event {
+            Event::Config { .. } | Event::Exit { .. } => {}
+            Event::PlanSummary(summary) => print_plan_summary(summary),
+            Event::VarFiles { var_files } => {
+                println!("Var files:");
+                for var_file in var_files {
+                    println!("  {}", var_file.display());
+                }
+            }
+            Event::Protected { target } => println!(
+                "{}",
+                style(format!(" PROTECTED {} ", target)).white().on_red().bold()
+            ),
+            Event::Exec {
+                argv,
+                version,
+                source,
+            } => println!(
+                "{}  # {}, from {}",
+                argv.join(" "),
+                version.as_deref().unwrap_or("unknown version"),
+                source
+            ),
+            Event::Status(status) => print_status(status),
+            Event::Inventory(inventory) => print_inventory(inventory),
+            Event::Profiles { profiles, current } => {
+                for (name, profile) in profiles {
+                    println!(
+                        "{} {}\t{}/{}",
+                        if Some(name.as_str()) == current { "*" } else { " " },
+                        name,
+                        profile.environment,
+                        profile.region
+                    );
+                }
+            }
+            Event::Matrix { results } => print_matrix(results),
+            Event::StateFiles { state_files } => {
+                for state_file in state_files {
+                    let root = if state_file.root_exists {
+                        style(state_file.root.display().to_string()).dim()
+                    } else {
+                        style(format!("{} (missing)", state_file.root.display())).yellow()
+                    };
+                    println!("{}  {}", state_file.state_key, root);
+                    println!("  {}", state_file.path.display());
+                }
+            }
+            Event::RepoState { state_file, state } => {
+                println!("State file: {}", state_file.display());
+                print_toml(state);
+            }
+            Event::Message { message } => println!("{}", message),
+            Event::Error { message } => eprintln!("Error: {}", message),
+        }
+    }
+
+    pub fn message(&self, message: impl Into<String>) {
+        self.event(Event::Message {
+            message: message.into(),
+        });
+    }
+}
+
+/// Prints `value` as TOML, or why it can't be, without failing the command
+fn print_toml(value: &impl Serialize) {
+    match toml::to_string(value) {
+        Ok(toml) => print!("{}", toml),
+        Err(err) => eprintln!("Error: could not show it as TOML: {}", err),
+    }
+}
+
+fn print_inventory(inventory: &Inventory) {
+    for module in &inventory.modules {
+        let sources = if module.sources.is_empty() {
+            style("no module source in the repo".to_string()).yellow()
+        } else {
+            style(
+                module
+                    .sources
+                    .iter()
+                    .map(|v| v.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )
+            .dim()
+        };
+        println!("{}  {}", style(&module.name).bold(), sources);
+
+        let width = module.targets.iter().map(|v| v.target.len()).max().unwrap_or_default();
+        for target in &module.targets {
+            println!(
+                "  {:width$}  {}",
+                target.target,
+                target.var_files.join(", "),
+                width = width
+            );
+        }
+        if !module.missing_environments.is_empty() {
+            println!(
+                "  {}",
+                style(format!(
+                    "no var files for: {}",
+                    module.missing_environments.join(", ")
+                ))
+                .yellow()
+            );
+        }
+    }
+}
+
+fn print_matrix(results: &[MatrixResult]) {
+    let width = results.iter().map(|v| v.target.len()).max().unwrap_or_default();
+    println!();
+    for result in results {
+        let status = match &result.result {
+            TargetResult::NoChanges => style("no changes".to_string()).green(),
+            TargetResult::Changes => style("changes".to_string()).yellow(),
+            TargetResult::Failed { step } => style(format!("failed ({})", step)).red(),
+            TargetResult::Interrupted => style("interrupted".to_string()).red(),
+        };
+        println!("{:width$}  {}", result.target, status, width = width);
+    }
+}
+
+fn print_plan_summary(summary: &PlanSummary) {
+    if !summary.has_changes() {
+        println!("{}", style("No changes").green());
+        return;
+    }
+
+    println!(
+        "{}: {} to add, {} to change, {} to destroy",
+        style("Plan").bold(),
+        summary.add,
+        summary.change,
+        summary.destroy
+    );
+    for (action, addresses) in summary.groups() {
+        if addresses.is_empty() {
+            continue;
+        }
+        let (symbol, color) = match action {
+            "create" => ("+", Style::new().green()),
+            "update" => ("~", Style::new().yellow()),
+            _ => (if action == "replace" { "-/+" } else { "-" }, Style::new().red().bold()),
+        };
+        println!("{} ({})", color.apply_to(action), addresses.len());
+        for address in addresses {
+            println!("  {}", color.apply_to(format!("{} {}", symbol, address)));
+        }
+    }
+}
+
+fn print_status(status: &Status) {
+    let marker = |file: &FileStatus| {
+        if file.exists {
+            style("[exists]").green()
+        } else {
+            style("[missing]").red()
+        }
+    };
+
+    println!("Target: {}", status.target);
+    print_toml(&status.config);
+    println!("State file: {}", status.state_file.display());
+    println!(
+        "Backend config: {} {}",
+        status.backend_config.path.display(),
+        marker(&status.backend_config)
+    );
+    println!("Var files:");
+    for var_file in &status.var_files {
+        println!("  {} {}", var_file.path.display(), marker(var_file));
+    }
+    println!("Workspace: {}", status.workspace);
+
+    let init = match &status.init {
+        InitStatus::NotInitialized => style("not initialized".to_string()).red(),
+        InitStatus::Unknown => {
+            style("initialized outside condeform, backend unknown".to_string()).yellow()
+        }
+        InitStatus::Current => {
+            style("initialized with the current backend config".to_string()).green()
+        }
+        InitStatus::Different { backend_config } => style(format!(
+            "initialized with a different backend config: {}",
+            backend_config
+        ))
+        .red(),
+    };
+    println!("{}: {}", status.data_dir.display(), init);
+
+    let plan = match &status.plan {
+        PlanStatus::None => style("none".to_string()).dim(),
+        PlanStatus::Matches => style("matches the current target".to_string()).green(),
+        PlanStatus::Stale { reason } => style(reason.to_owned()).red(),
+    };
+    println!("{}: {}", crate::plan::PLAN_PATH, plan);
+}
@@ -0,0 +1,184 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::ModuleError;
+use crate::layout::Layout;
+use crate::repo::get_git_commit;
+use crate::Config;
+
+pub const PLAN_PATH: &str = "./plan.plan";
+pub const MANIFEST_PATH: &str = "./plan.plan.toml";
+
+/// Written next to the plan file, recording what the plan was created from.
+#[derive(Deserialize, Serialize)]
+pub struct PlanManifest {
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+    pub git_commit: Option<String>,
+    pub config: Config,
+    pub var_files: Vec<VarFile>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq)]
+pub struct VarFile {
+    pub path: String,
+    /// Hex encoded sha256 of the file's content
+    pub sha256: String,
+}
+
+impl VarFile {
+    pub fn read(path: &Path) -> anyhow::Result<VarFile> {
+        Ok(VarFile {
+            path: path.to_string_lossy().to_string(),
+            sha256: hash_file(path)?,
+        })
+    }
+}
+
+/// Writes the manifest for the plan at `plan_path`, as `<plan_path>.toml`.
+pub fn write_manifest(plan_path: &str, config: &Config, var_files: &[PathBuf]) -> anyhow::Result<()> {
+    let manifest = PlanManifest {
+        created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        git_commit: get_git_commit(),
+        config: config.clone(),
+        var_files: var_files
+            .iter()
+            .map(|v| VarFile::read(v))
+            .collect::<anyhow::Result<_>>()?,
+    };
+    fs::write(format!("{}.toml", plan_path), toml::to_string(&manifest)?)?;
+    Ok(())
+}
+
+pub fn read_manifest() -> anyhow::Result<PlanManifest> {
+    if !Path::new(MANIFEST_PATH).exists() {
+        return Err(ModuleError::MissingPlanManifest(PLAN_PATH.to_string()).into());
+    }
+    Ok(toml::from_str(&fs::read_to_string(MANIFEST_PATH)?)?)
+}
+
+/// Refuses a plan that was made against a different target, or whose var files have since changed.
+pub fn check_manifest(layout: &Layout, config: &Config, var_files: &[PathBuf]) -> anyhow::Result<()> {
+    let manifest = read_manifest()?;
+    if !same_target(&manifest.config, config) {
+        return Err(ModuleError::StalePlan {
+            planned: layout.describe(&manifest.config),
+            current: layout.describe(config),
+        }
+        .into());
+    }
+    let planned_paths: Vec<&str> = manifest.var_files.iter().map(|v| v.path.as_str()).collect();
+    let current_paths: Vec<String> = var_files
+        .iter()
+        .map(|v| v.to_string_lossy().to_string())
+        .collect();
+    if planned_paths != current_paths {
+        return Err(ModuleError::ChangedVarFiles {
+            planned: planned_paths.join(", "),
+            current: current_paths.join(", "),
+        }
+        .into());
+    }
+    for planned in &manifest.var_files {
+        if *planned != VarFile::read(Path::new(&planned.path))? {
+            return Err(ModuleError::ChangedVarFile(planned.path.to_owned()).into());
+        }
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path) -> anyhow::Result<String> {
+    let content = fs::read(path)?;
+    Ok(format!("{:x}", Sha256::digest(content)))
+}
+
+fn same_target(a: &Config, b: &Config) -> bool {
+    a.environment == b.environment
+        && a.region == b.region
+        && a.module == b.module
+        && a.segments == b.segments
+}
+
+/// Resource changes in a plan, grouped by action. The counts follow terraform's convention of
+/// counting a replacement as both an add and a destroy.
+#[derive(Serialize, Default)]
+pub struct PlanSummary {
+    pub add: usize,
+    pub change: usize,
+    pub destroy: usize,
+    /// Resource addresses for each action
+    pub create: Vec<String>,
+    pub update: Vec<String>,
+    pub replace: Vec<String>,
+    pub delete: Vec<String>,
+}
+
+impl PlanSummary {
+    pub fn has_changes(&self) -> bool {
+        self.add + self.change + self.destroy > 0
+    }
+
+    /// The actions with their resources, in the order they're shown.
+    pub fn groups(&self) -> [(&'static str, &[String]); 4] {
+        [
+            ("create", &self.create),
+            ("update", &self.update),
+            ("replace", &self.replace),
+            ("delete", &self.delete),
+        ]
+    }
+}
+
+/// Groups the resource changes in the output of `terraform show -json` by action.
+pub fn summarize(plan_json: &serde_json::Value) -> PlanSummary {
+    let mut summary = PlanSummary::default();
+    let changes = plan_json["resource_changes"].as_array();
+    for change in changes.into_iter().flatten() {
+        let address = change["address"].as_str().unwrap_or_default().to_string();
+        let actions: Vec<&str> = change["change"]["actions"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str())
+            .collect();
+        match actions[..] {
+            ["create"] => summary.create.push(address),
+            ["update"] => summary.update.push(address),
+            ["delete"] => summary.delete.push(address),
+            ["delete", "create"] | ["create", "delete"] => summary.replace.push(address),
+            _ => {}
+        }
+    }
+
+    summary.add = summary.create.len() + summary.replace.len();
+    summary.change = summary.update.len();
+    summary.destroy = summary.delete.len() + summary.replace.len();
+    summary
+}
+
+/// A Markdown version of the summary, e.g. for a pull request comment.
+pub fn to_markdown(target: &str, summary: &PlanSummary) -> String {
+    let mut markdown = format!(
+        "#### Plan for `{}`\n\n{} to add, {} to change, {} to destroy\n",
+        target, summary.add, summary.change, summary.destroy
+    );
+    for (action, addresses) in summary.groups() {
+        if addresses.is_empty() {
+            continue;
+        }
+        let warning = if ["replace", "delete"].contains(&action) {
+            " :warning:"
+        } else {
+            ""
+        };
+        markdown.push_str(&format!("\n**{}**{}\n", action, warning));
+        for address in addresses {
+            markdown.push_str(&format!("- `{}`\n", address));
+        }
+    }
+    markdown
+}
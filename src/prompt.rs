@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+
+use condeform::error::ModuleError;
+use condeform::layout::{Layout, Segment};
+use condeform::repo_config::RepoConfig;
+use condeform::repo::{get_dirnames_from_path, utf8};
+use condeform::Config;
+
+/// Prompts for a segment's value from the directories in `dir`, or as text on <ESC>. If the
+/// repo config restricts the segment's values, only those are offered.
+fn segment_input(
+    name: &str,
+    current: Option<&str>,
+    dir: &Path,
+    repo_config: &RepoConfig,
+    theme: &ColorfulTheme,
+) -> anyhow::Result<String> {
+    let allowed = repo_config.allowed(name);
+    let mut items: Vec<String> = match allowed {
+        Some(allowed) => allowed.to_vec(),
+        None if dir.is_dir() => get_dirnames_from_path(dir)?
+            .into_iter()
+            .filter(|v| !repo_config.exclude.contains(v))
+            .collect(),
+        None => vec![],
+    };
+
+    let mut uniq = HashSet::new();
+    items.sort_unstable();
+
+    if let Some(current) = current {
+        if allowed.is_none_or(|v| v.iter().any(|v| v == current)) {
+            items.insert(0, current.to_owned());
+        }
+    }
+    items.retain(|v| uniq.insert(v.to_owned()));
+
+    let prompt = if allowed.is_some() {
+        format!("Select {}", name)
+    } else {
+        format!("Select {} or <ESC> for text input", name)
+    };
+    let index = if items.is_empty() {
+        None
+    } else {
+        Select::with_theme(theme)
+            .with_prompt(prompt)
+            .items(&items)
+            .default(0)
+            .interact_opt()?
+    };
+
+    match index {
+        Some(idx) => Ok(items[idx].to_owned()),
+        None if allowed.is_some() => Err(ModuleError::IncompleteConfig(name.to_string()).into()),
+        None => {
+            let mut input = Input::<String>::with_theme(theme);
+            input.with_prompt(name);
+            if let Some(default) = items.first() {
+                input.default(default.to_owned());
+            }
+            Ok(input.interact_text()?)
+        }
+    }
+}
+
+pub fn get_config_with_input(
+    state: &Config,
+    cwd: &Path,
+    layout: &Layout,
+    repo_config: &RepoConfig,
+) -> anyhow::Result<Config> {
+    let theme = ColorfulTheme::default();
+
+    let infra_dir = Input::<String>::with_theme(&theme)
+        .with_prompt("Infra Dir")
+        .default(state.infra_dir.to_string())
+        .interact_text()?;
+
+    let infra_path = cwd
+        .join(&infra_dir)
+        .canonicalize()
+        .map_err(|_| ModuleError::MissingInfraDir(infra_dir))?;
+
+    let mut config = Config {
+        infra_dir: utf8(&infra_path)?.to_string(),
+        ..state.clone()
+    };
+    let mut dir = infra_path;
+    for segment in layout.segments() {
+        match segment {
+            Segment::Literal(value) => dir.push(value),
+            Segment::Named(name) if name == "module" => {
+                config.module = Input::<String>::with_theme(&theme)
+                    .with_prompt("Module")
+                    .with_initial_text(
+                        cwd.file_name()
+                            .and_then(|v| v.to_str())
+                            .unwrap_or(&state.module),
+                    )
+                    .default(state.module.to_string())
+                    .interact_text()?;
+            }
+            Segment::Named(name) => {
+                let value = segment_input(name, state.segment(name), &dir, repo_config, &theme)?;
+                dir.push(&value);
+                config.set_segment(name, value);
+            }
+        }
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,132 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::ModuleError;
+use crate::repo_config::REPO_CONFIG_FILENAME;
+
+pub fn get_git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(vec!["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|v| v.trim().to_string())
+}
+
+/// Where condeform is running: the directory module keys and the repo config are relative to,
+/// and the key its state is stored under.
+pub struct Repo {
+    /// The git worktree's top level, or the directory with the marker file outside git
+    pub root: PathBuf,
+    /// The main checkout's path, followed by `#<worktree name>` in linked worktrees
+    pub state_key: String,
+}
+
+impl Repo {
+    /// Finds the repo containing `cwd` with git, falling back to the nearest directory above it
+    /// with a `.condeform.toml`, then the nearest with a `.terraform`, when git isn't installed
+    /// or `cwd` isn't in a repo. `state_key` replaces the key derived from the repo's path.
+    pub fn find(cwd: &Path, state_key: Option<&str>) -> Result<Repo, ModuleError> {
+        let mut repo = match find_git(cwd)? {
+            Some(repo) => repo,
+            None => {
+                let root =
+                    find_marker(cwd).ok_or_else(|| ModuleError::RepoNotFound(cwd.to_path_buf()))?;
+                Repo {
+                    state_key: utf8(&root)?.to_string(),
+                    root,
+                }
+            }
+        };
+        if let Some(state_key) = state_key {
+            repo.state_key = state_key.to_string();
+        }
+        Ok(repo)
+    }
+}
+
+fn find_git(cwd: &Path) -> Result<Option<Repo>, ModuleError> {
+    let output = match Command::new("git")
+        .args([
+            "rev-parse",
+            "--show-toplevel",
+            "--git-dir",
+            "--git-common-dir",
+        ])
+        .current_dir(cwd)
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Ok(None),
+    };
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| ModuleError::NonUtf8Path(cwd.to_path_buf()))?;
+    // relative paths are relative to `cwd`
+    let paths: Vec<PathBuf> = stdout
+        .lines()
+        .filter_map(|v| cwd.join(v).canonicalize().ok())
+        .collect();
+    let [root, git_dir, common_dir] = paths.as_slice() else {
+        return Ok(None);
+    };
+
+    // a linked worktree has its own git dir in the main checkout's `.git/worktrees`
+    let state_key = if git_dir == common_dir {
+        utf8(root)?.to_string()
+    } else {
+        let main = match common_dir.file_name() {
+            Some(name) if name == ".git" => common_dir.parent().unwrap_or(common_dir),
+            _ => common_dir,
+        };
+        let worktree = git_dir.file_name().unwrap_or_default();
+        format!("{}#{}", utf8(main)?, utf8(Path::new(worktree))?)
+    };
+
+    Ok(Some(Repo {
+        root: root.to_owned(),
+        state_key,
+    }))
+}
+
+fn find_marker(cwd: &Path) -> Option<PathBuf> {
+    let cwd = cwd.canonicalize().ok()?;
+    [REPO_CONFIG_FILENAME, ".terraform"]
+        .iter()
+        .find_map(|marker| {
+            cwd.ancestors()
+                .find(|dir| dir.join(marker).exists())
+                .map(|dir| dir.to_path_buf())
+        })
+}
+
+/// Identifies a module within the repo by its path relative to the repo root.
+pub fn get_module_key(repo_root: &Path, cwd: &Path) -> String {
+    let cwd = cwd.canonicalize().unwrap_or_else(|_| cwd.to_path_buf());
+    cwd.strip_prefix(repo_root)
+        .unwrap_or(&cwd)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// The path as a `str`, for terraform arguments and the state file.
+pub fn utf8(path: &Path) -> Result<&str, ModuleError> {
+    path.to_str()
+        .ok_or_else(|| ModuleError::NonUtf8Path(path.to_path_buf()))
+}
+
+/// The names of the directories in `path`.
+pub fn get_dirnames_from_path(path: &Path) -> io::Result<Vec<String>> {
+    Ok(path
+        .read_dir()?
+        .filter_map(|v| v.ok())
+        .map(|v| v.path())
+        .filter(|v| v.is_dir())
+        .filter_map(|v| v.file_name().and_then(|v| v.to_str()).map(|v| v.to_string()))
+        .collect())
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::ModuleError;
+use crate::layout::{Layout, DEFAULT_LAYOUT};
+use crate::Config;
+
+pub const REPO_CONFIG_FILENAME: &str = ".condeform.toml";
+
+/// Settings shared by everyone working in a repo, read from `.condeform.toml` at the git root.
+/// Personal state and command line overrides are layered on top.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RepoConfig {
+    /// Infra dir, relative to the repo root
+    pub infra_dir: Option<String>,
+    /// Default environment for modules that haven't been used yet
+    pub environment: Option<String>,
+    /// Default region for modules that haven't been used yet
+    pub region: Option<String>,
+    /// If set, the only environments that can be targeted
+    pub environments: Option<Vec<String>>,
+    /// If set, the only regions that can be targeted
+    pub regions: Option<Vec<String>>,
+    /// Environments that need confirming before apply or destroy
+    pub protected: Vec<String>,
+    /// Directory structure below the infra dir, see [`Layout`]
+    pub layout: String,
+    /// Directory names that are never offered as a segment value
+    pub exclude: Vec<String>,
+    /// Replaces condeform's default terraform flags, keyed by command, e.g. `plan`
+    pub args: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for RepoConfig {
+    fn default() -> Self {
+        RepoConfig {
+            infra_dir: None,
+            environment: None,
+            region: None,
+            environments: None,
+            regions: None,
+            protected: vec![],
+            layout: DEFAULT_LAYOUT.to_string(),
+            exclude: vec!["terraform".to_string()],
+            args: BTreeMap::new(),
+        }
+    }
+}
+
+impl RepoConfig {
+    pub fn layout(&self) -> anyhow::Result<Layout> {
+        Ok(Layout::parse(&self.layout)?)
+    }
+
+    /// The starting config for a repo with no personal state.
+    pub fn defaults(&self, repo_root: &Path) -> Config {
+        let default = Config::default();
+        Config {
+            environment: self.environment.to_owned().or(default.environment),
+            region: self.region.to_owned().unwrap_or(default.region),
+            infra_dir: self.infra_dir.as_ref().map_or(default.infra_dir, |v| {
+                repo_root.join(v).to_string_lossy().to_string()
+            }),
+            ..default
+        }
+    }
+
+    pub fn allowed(&self, segment: &str) -> Option<&[String]> {
+        match segment {
+            "environment" => self.environments.as_deref(),
+            "region" => self.regions.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn check_allowed(&self, config: &Config) -> Result<(), ModuleError> {
+        for name in ["environment", "region"] {
+            if let (Some(allowed), Some(value)) = (self.allowed(name), config.segment(name)) {
+                if !allowed.iter().any(|v| v == value) {
+                    return Err(ModuleError::NotAllowed {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                        allowed: allowed.join(", "),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The repo's default flags for `command`, or condeform's own if the repo doesn't set any.
+    pub fn default_args(&self, command: &str, builtin: &[&str]) -> Vec<String> {
+        self.args.get(command).map_or_else(
+            || builtin.iter().map(|v| v.to_string()).collect(),
+            |v| v.to_owned(),
+        )
+    }
+}
+
+pub fn read_repo_config(repo_root: &Path) -> anyhow::Result<RepoConfig> {
+    let path = repo_root.join(REPO_CONFIG_FILENAME);
+    if !path.is_file() {
+        return Ok(RepoConfig::default());
+    }
+    toml::from_str(&fs::read_to_string(&path)?).map_err(|err| {
+        ModuleError::InvalidRepoConfig {
+            path,
+            reason: err.to_string(),
+        }
+        .into()
+    })
+}
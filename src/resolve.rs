@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::ModuleError;
+use crate::layout::Layout;
+use crate::repo::utf8;
+use crate::Config;
+
+/// The module's `<basename>.tfvars`, e.g. `backend.tfvars`, which must exist.
+pub fn get_module_var_dir(layout: &Layout, config: &Config, basename: &str) -> Result<PathBuf, ModuleError> {
+    if !Path::new(&config.infra_dir).is_dir() {
+        return Err(ModuleError::MissingInfraDir(config.infra_dir.to_owned()));
+    }
+
+    let mut module_path = layout.module_dir(config)?;
+
+    if !module_path.is_dir() {
+        return Err(ModuleError::NotADirectory(module_path.to_string_lossy().to_string()));
+    }
+
+    module_path.push(basename);
+    module_path.set_extension("tfvars");
+    if !module_path.is_file() {
+        return Err(ModuleError::MissingVarFile(module_path.to_string_lossy().to_string()));
+    }
+    Ok(module_path)
+}
+
+/// Every var file for the module, in precedence order: `common.tfvars` in the infra dir and
+/// each directory of the layout below it, if they exist, then the module's own `terraform.tfvars`.
+pub fn get_module_var_files(layout: &Layout, config: &Config) -> Result<Vec<PathBuf>, ModuleError> {
+    let module_file = get_module_var_dir(layout, config, "terraform")?;
+
+    let mut var_files = get_common_var_files(layout, config)?;
+    var_files.push(module_file);
+    Ok(var_files)
+}
+
+/// The `common.tfvars` files that exist in the directories above the module.
+pub fn get_common_var_files(layout: &Layout, config: &Config) -> Result<Vec<PathBuf>, ModuleError> {
+    let mut layer_dirs = layout.dirs(config)?;
+    layer_dirs.pop();
+
+    Ok(layer_dirs
+        .into_iter()
+        .map(|v| v.join("common.tfvars"))
+        .filter(|v| v.is_file())
+        .collect())
+}
+
+pub fn var_file_args(var_files: &[PathBuf]) -> Result<Vec<String>, ModuleError> {
+    let mut args = vec![];
+    for var_file in var_files {
+        args.extend(["-var-file".to_string(), utf8(var_file)?.to_string()]);
+    }
+    Ok(args)
+}
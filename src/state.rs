@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use etcetera::app_strategy::{AppStrategy, AppStrategyArgs, Xdg};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::ModuleError;
+use crate::repo::Repo;
+use crate::Config;
+
+const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
+const APP_NAME: &str = env!("CARGO_PKG_NAME");
+/// Maps each state file in the state dir to its repo
+pub const INDEX_FILENAME: &str = "index.toml";
+
+/// Everything remembered for a single repository.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct State {
+    /// Terraform executable for every module in the repo, unless the module sets its own. Comes
+    /// before the tables, as TOML can't have a plain value after them
+    pub terraform_bin: Option<String>,
+    /// Repo-wide fallback for modules that have not been used yet
+    pub defaults: Option<Config>,
+    /// Keyed by the module's path relative to the repo root
+    #[serde(default)]
+    pub modules: BTreeMap<String, Config>,
+    /// Named environment/region combinations, shared by every module in the repo
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct Profile {
+    pub environment: String,
+    pub region: String,
+}
+
+impl State {
+    /// The config last used for `module_key`, falling back to the repo-wide defaults, then
+    /// `fallback` for a repo that hasn't been used yet.
+    pub fn config_for(&self, module_key: &str, module_name: &str, fallback: &Config) -> Config {
+        if let Some(config) = self.modules.get(module_key) {
+            return config.clone();
+        }
+        Config {
+            module: module_name.to_string(),
+            ..self.defaults.clone().unwrap_or_else(|| fallback.clone())
+        }
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile, ModuleError> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| ModuleError::UnknownProfile(name.to_string()))
+    }
+
+    pub fn set(&mut self, module_key: &str, config: &Config) {
+        self.modules.insert(module_key.to_string(), config.clone());
+        self.defaults = Some(config.clone());
+    }
+}
+
+/// Reads the state file, migrating the single-config format used before per-module state.
+pub fn read_state(state_path: &Path) -> anyhow::Result<State> {
+    let str = match fs::read_to_string(state_path) {
+        Ok(str) => str,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(State::default()),
+        Err(err) => return Err(err.into()),
+    };
+
+    if let Ok(legacy) = toml::from_str::<Config>(&str) {
+        let state = State {
+            defaults: Some(legacy),
+            ..State::default()
+        };
+        write_state(state_path, &state)?;
+        return Ok(state);
+    }
+
+    toml::from_str(&str).map_err(|err| {
+        ModuleError::CorruptState {
+            path: state_path.to_path_buf(),
+            reason: err.to_string(),
+        }
+        .into()
+    })
+}
+
+pub fn write_state(state_path: &Path, state: &State) -> anyhow::Result<()> {
+    fs::write(state_path, toml::to_string(state)?)?;
+    Ok(())
+}
+
+/// Where the state files for every repo are kept, e.g. `~/.local/state/condeform`. Created if
+/// it doesn't exist.
+pub fn state_dir() -> anyhow::Result<PathBuf> {
+    let strategy = Xdg::new(AppStrategyArgs {
+        top_level_domain: "org".to_string(),
+        author: AUTHORS.to_string(),
+        app_name: APP_NAME.to_string(),
+    })
+    .map_err(|_| ModuleError::NoStateDir)?;
+
+    let state_dir = strategy.state_dir().ok_or(ModuleError::NoStateDir)?;
+
+    fs::create_dir_all(&state_dir)?;
+    Ok(state_dir)
+}
+
+/// The state files in the state dir, keyed by file name.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct Index {
+    #[serde(default)]
+    pub repos: BTreeMap<String, IndexEntry>,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct IndexEntry {
+    pub state_key: String,
+    pub root: PathBuf,
+}
+
+/// A state file and the repo it belongs to, as listed by `condeform state list`.
+#[derive(Serialize, Debug)]
+pub struct StateFile {
+    pub path: PathBuf,
+    pub state_key: String,
+    pub root: PathBuf,
+    /// Whether the repo is still there. Its state is removed by `condeform state prune` if not
+    pub root_exists: bool,
+}
+
+/// The state file's name: the last part of the key, for people looking in the state dir,
+/// followed by a hash of the whole key, so that keys with the same last part don't collide.
+pub fn state_filename(state_key: &str) -> String {
+    let readable: String = Path::new(state_key)
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default()
+        .chars()
+        .map(|v| {
+            if v.is_ascii_alphanumeric() || "-_.".contains(v) {
+                v
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let hash = format!("{:x}", Sha256::digest(state_key.as_bytes()));
+    format!(
+        "{}-{}.toml",
+        if readable.is_empty() {
+            "repo"
+        } else {
+            &readable
+        },
+        &hash[..12]
+    )
+}
+
+/// The name state files had before the index, with each `/` of the key replaced by `%`.
+fn legacy_state_filename(state_key: &str) -> String {
+    format!("{}.toml", state_key.replace('/', "%"))
+}
+
+/// The repo's state file, recording it in the index. A state file under the repo's legacy name
+/// is renamed.
+pub fn repo_state_path(state_dir: &Path, repo: &Repo) -> anyhow::Result<PathBuf> {
+    let filename = state_filename(&repo.state_key);
+    let state_path = state_dir.join(&filename);
+
+    let legacy_path = state_dir.join(legacy_state_filename(&repo.state_key));
+    if !state_path.exists() && legacy_path.is_file() {
+        fs::rename(&legacy_path, &state_path)?;
+    }
+
+    let mut index = read_index(state_dir)?;
+    let entry = IndexEntry {
+        state_key: repo.state_key.to_owned(),
+        root: repo.root.to_owned(),
+    };
+    if index.repos.get(&filename) != Some(&entry) {
+        index.repos.insert(filename, entry);
+        write_index(state_dir, &index)?;
+    }
+    Ok(state_path)
+}
+
+/// Reads the index, adding the state files of repos that haven't been used since it was
+/// introduced. Their names only encode the key, which was the repo's root.
+pub fn read_index(state_dir: &Path) -> anyhow::Result<Index> {
+    let index_path = state_dir.join(INDEX_FILENAME);
+    let mut index: Index = match fs::read_to_string(&index_path) {
+        Ok(str) => toml::from_str(&str).map_err(|err| ModuleError::CorruptState {
+            path: index_path.to_owned(),
+            reason: err.to_string(),
+        })?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Index::default(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut migrated = false;
+    for entry in state_dir.read_dir()?.filter_map(|v| v.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(root) = name
+            .strip_suffix(".toml")
+            .filter(|v| v.starts_with('%') && !v.contains('#'))
+            .map(|v| PathBuf::from(v.replace('%', "/")))
+        else {
+            continue;
+        };
+        let state_key = migrated_state_key(&root);
+        let filename = state_filename(&state_key);
+        if state_dir.join(&filename).exists() {
+            continue;
+        }
+        fs::rename(entry.path(), state_dir.join(&filename))?;
+        index.repos.insert(filename, IndexEntry { state_key, root });
+        migrated = true;
+    }
+    if migrated {
+        write_index(state_dir, &index)?;
+    }
+    Ok(index)
+}
+
+/// The key for a legacy state file of the repo at `root`. Linked worktrees were keyed on their
+/// own path, and are now keyed on the main checkout's, so the key is found again with git while
+/// the worktree still exists.
+fn migrated_state_key(root: &Path) -> String {
+    match Repo::find(root, None) {
+        Ok(repo) if root.canonicalize().is_ok_and(|v| v == repo.root) => repo.state_key,
+        _ => root.to_string_lossy().to_string(),
+    }
+}
+
+pub fn write_index(state_dir: &Path, index: &Index) -> anyhow::Result<()> {
+    fs::write(state_dir.join(INDEX_FILENAME), toml::to_string(index)?)?;
+    Ok(())
+}
+
+/// Every state file in the index.
+pub fn list(state_dir: &Path) -> anyhow::Result<Vec<StateFile>> {
+    Ok(read_index(state_dir)?
+        .repos
+        .into_iter()
+        .map(|(filename, entry)| StateFile {
+            path: state_dir.join(filename),
+            root_exists: entry.root.is_dir(),
+            state_key: entry.state_key,
+            root: entry.root,
+        })
+        .collect())
+}
+
+/// Removes the state of repos that no longer exist, returning what was removed. With `dry_run`,
+/// only returns what would be.
+pub fn prune(state_dir: &Path, dry_run: bool) -> anyhow::Result<Vec<StateFile>> {
+    let pruned: Vec<StateFile> = list(state_dir)?
+        .into_iter()
+        .filter(|v| !v.root_exists)
+        .collect();
+    if dry_run || pruned.is_empty() {
+        return Ok(pruned);
+    }
+
+    let mut index = read_index(state_dir)?;
+    for state_file in &pruned {
+        remove_state_file(&state_file.path)?;
+        if let Some(filename) = state_file.path.file_name() {
+            index.repos.remove(&filename.to_string_lossy().to_string());
+        }
+    }
+    write_index(state_dir, &index)?;
+    Ok(pruned)
+}
+
+/// Deletes the repo's state, so that its modules start over from the repo config's defaults.
+pub fn reset(state_dir: &Path, state_path: &Path) -> anyhow::Result<()> {
+    remove_state_file(state_path)?;
+
+    let mut index = read_index(state_dir)?;
+    let filename = state_path.file_name().unwrap_or_default().to_string_lossy();
+    if index.repos.remove(filename.as_ref()).is_some() {
+        write_index(state_dir, &index)?;
+    }
+    Ok(())
+}
+
+fn remove_state_file(state_path: &Path) -> io::Result<()> {
+    match fs::remove_file(state_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
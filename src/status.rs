@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::backend;
+use crate::layout::Layout;
+use crate::plan;
+use crate::Config;
+
+#[derive(Serialize)]
+pub struct Status {
+    pub target: String,
+    pub config: Config,
+    pub state_file: PathBuf,
+    pub backend_config: FileStatus,
+    pub var_files: Vec<FileStatus>,
+    pub workspace: String,
+    pub data_dir: PathBuf,
+    pub init: InitStatus,
+    pub plan: PlanStatus,
+}
+
+#[derive(Serialize)]
+pub struct FileStatus {
+    pub path: PathBuf,
+    pub exists: bool,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum InitStatus {
+    NotInitialized,
+    /// Initialized outside condeform, so the backend config isn't known
+    Unknown,
+    Current,
+    Different { backend_config: String },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum PlanStatus {
+    None,
+    Matches,
+    Stale { reason: String },
+}
+
+pub fn get_status(
+    layout: &Layout,
+    config: &Config,
+    state_path: &Path,
+    data_dir: &Path,
+) -> anyhow::Result<Status> {
+    let file_status = |path: PathBuf| FileStatus {
+        exists: path.exists(),
+        path,
+    };
+
+    // an incomplete target is shown rather than refused, as status is how to find out what's
+    // missing from it
+    let mut dirs = layout.dirs_or_none(config);
+    let module_dir = dirs.pop().unwrap_or_default();
+    let backend_config = module_dir.join("backend.tfvars");
+    let mut var_files: Vec<PathBuf> = dirs
+        .into_iter()
+        .map(|v| v.join("common.tfvars"))
+        .filter(|v| v.is_file())
+        .collect();
+    var_files.push(module_dir.join("terraform.tfvars"));
+
+    let init = match backend::read_init_marker(data_dir) {
+        _ if !data_dir.is_dir() => InitStatus::NotInitialized,
+        None => InitStatus::Unknown,
+        Some(init) if init.matches(&backend_config) => InitStatus::Current,
+        Some(init) => InitStatus::Different {
+            backend_config: init.backend_config.path,
+        },
+    };
+
+    let plan = if !Path::new(plan::PLAN_PATH).exists() {
+        PlanStatus::None
+    } else {
+        match plan::check_manifest(layout, config, &var_files) {
+            Ok(()) => PlanStatus::Matches,
+            Err(err) => PlanStatus::Stale {
+                reason: err.to_string(),
+            },
+        }
+    };
+
+    Ok(Status {
+        target: layout.describe(config),
+        config: config.clone(),
+        state_file: state_path.to_path_buf(),
+        backend_config: file_status(backend_config),
+        var_files: var_files.into_iter().map(file_status).collect(),
+        workspace: backend::workspace(data_dir),
+        data_dir: data_dir.to_path_buf(),
+        init,
+        plan,
+    })
+}
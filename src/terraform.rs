@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::io;
+use std::process::{Command, ExitCode, ExitStatus, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use clap::Args;
+
+use crate::error::ModuleError;
+use crate::executor;
+use crate::output::{Event, Output};
+use crate::state::State;
+use crate::Config;
+
+/// Version pinning files, and the executable whose shim reads them
+const VERSION_FILES: &[(&str, &str)] = &[(".opentofu-version", "tofu"), (".terraform-version", "terraform")];
+/// Executables looked up on the PATH, in order of preference
+const EXECUTABLES: &[&str] = &["terraform", "tofu"];
+
+/// The terraform compatible executable to run, and where it was configured.
+pub struct TerraformRunner {
+    pub binary: String,
+    pub source: String,
+    /// `version`'s result, as running the binary can be slow through a version manager's shim
+    version: OnceLock<Option<String>>,
+}
+
+impl TerraformRunner {
+    /// Resolves the executable from the module's config (which includes `--terraform-bin`
+    /// and `CONDEFORM_TERRAFORM_BIN`), then the repo's state, then version pinning files
+    /// above `cwd`, then whatever is on the PATH.
+    pub fn detect(config: &Config, state: &State, cwd: &Path) -> TerraformRunner {
+        if let Some(binary) = &config.terraform_bin {
+            return TerraformRunner::new(binary, "module config or --terraform-bin");
+        }
+        if let Some(binary) = &state.terraform_bin {
+            return TerraformRunner::new(binary, "repo state");
+        }
+
+        for dir in cwd.ancestors() {
+            for (filename, binary) in VERSION_FILES {
+                if dir.join(filename).is_file() {
+                    return TerraformRunner::new(binary, filename);
+                }
+            }
+        }
+
+        EXECUTABLES
+            .iter()
+            .find(|v| find_in_path(v).is_some())
+            .map_or(TerraformRunner::new("terraform", "default"), |v| {
+                TerraformRunner::new(v, "PATH")
+            })
+    }
+
+    fn new(binary: &str, source: &str) -> TerraformRunner {
+        TerraformRunner {
+            binary: binary.to_string(),
+            source: source.to_string(),
+            version: OnceLock::new(),
+        }
+    }
+
+    /// First line of `<binary> version`, e.g. `Terraform v1.5.7`. Only run once.
+    pub fn version(&self) -> Option<String> {
+        self.version.get_or_init(|| self.read_version()).clone()
+    }
+
+    fn read_version(&self) -> Option<String> {
+        let output = Command::new(&self.binary)
+            .arg("version")
+            .env("CHECKPOINT_DISABLE", "1")
+            .output()
+            .ok()?;
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        stdout.lines().next().map(|v| v.trim().to_string())
+    }
+
+    /// Explains a failure to start terraform because it isn't installed
+    fn not_found(&self, err: io::Error) -> anyhow::Error {
+        if err.kind() == io::ErrorKind::NotFound {
+            ModuleError::TerraformNotFound(self.binary.to_owned()).into()
+        } else {
+            err.into()
+        }
+    }
+
+    fn exec_event(&self, args: &[String], out: &Output) {
+        let mut argv = vec![self.binary.to_owned()];
+        argv.extend(args.iter().cloned());
+        out.event(Event::Exec {
+            argv,
+            version: self.version(),
+            source: &self.source,
+        });
+    }
+}
+
+/// Runs terraform invocations, so that commands can be tested without terraform installed
+pub trait Runner: Sync {
+    fn run(&self, args: &[String], out: &Output) -> anyhow::Result<ExitStatus>;
+
+    /// Like `run`, but for running alongside other targets, with `env` added to terraform's
+    /// environment. Returns `None` after Ctrl-C instead of running.
+    fn run_target(
+        &self,
+        args: &[String],
+        env: &[(&str, &Path)],
+        target: &str,
+        out: &Output,
+    ) -> anyhow::Result<Option<ExitStatus>>;
+
+    /// The output of `terraform show -json` for a saved plan.
+    fn show_json(&self, plan_path: &str) -> anyhow::Result<serde_json::Value>;
+}
+
+impl Runner for TerraformRunner {
+    /// Runs terraform, reporting the command and its exit status. With JSON output, terraform's
+    /// stdout goes to stderr so that stdout only has condeform's events.
+    fn run(&self, args: &[String], out: &Output) -> anyhow::Result<ExitStatus> {
+        self.exec_event(args, out);
+
+        let mut command = Command::new(&self.binary);
+        command.args(args);
+        if out.is_json() {
+            command.stdout(Stdio::from(io::stderr()));
+        }
+
+        let start = Instant::now();
+        let status = command.status().map_err(|err| self.not_found(err))?;
+        exit_event(status, start, out);
+        Ok(status)
+    }
+
+    /// Each line of terraform's output is prefixed with `target`.
+    fn run_target(
+        &self,
+        args: &[String],
+        env: &[(&str, &Path)],
+        target: &str,
+        out: &Output,
+    ) -> anyhow::Result<Option<ExitStatus>> {
+        if executor::interrupted() {
+            return Ok(None);
+        }
+        self.exec_event(args, out);
+
+        let mut command = Command::new(&self.binary);
+        command.args(args).envs(env.iter().copied());
+
+        let start = Instant::now();
+        let prefix = format!("[{}]", target);
+        let status = executor::run_prefixed(&mut command, &prefix, out.is_json())
+            .map_err(|err| self.not_found(err))?;
+        if let Some(status) = status {
+            exit_event(status, start, out);
+        }
+        Ok(status)
+    }
+
+    fn show_json(&self, plan_path: &str) -> anyhow::Result<serde_json::Value> {
+        let output = Command::new(&self.binary)
+            .args(["show", "-json", plan_path])
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|err| self.not_found(err))?;
+        if !output.status.success() {
+            return Err(ModuleError::ShowFailed(plan_path.to_string()).into());
+        }
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+}
+
+/// A terraform invocation recorded by `RecordingRunner`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    pub args: Vec<String>,
+    /// Added to terraform's environment, e.g. `TF_DATA_DIR` for a matrix target
+    pub env: Vec<(String, PathBuf)>,
+}
+
+/// Records each invocation instead of running it, and exits with the subcommand's code in
+/// `exit_codes`, or else `exit_code`. `show_json` returns `plan_json`, or fails as terraform
+/// would without a plan if it's `None`.
+#[derive(Default)]
+pub struct RecordingRunner {
+    pub exit_code: i32,
+    pub exit_codes: BTreeMap<String, i32>,
+    pub plan_json: Option<serde_json::Value>,
+    pub invocations: Mutex<Vec<Invocation>>,
+}
+
+impl RecordingRunner {
+    /// The arguments of each invocation so far
+    pub fn args(&self) -> Vec<Vec<String>> {
+        self.invocations.lock().unwrap().iter().map(|v| v.args.clone()).collect()
+    }
+
+    fn record(&self, args: &[String], env: &[(&str, &Path)]) -> ExitStatus {
+        self.invocations.lock().unwrap().push(Invocation {
+            args: args.to_vec(),
+            env: env.iter().map(|(name, value)| (name.to_string(), value.to_path_buf())).collect(),
+        });
+        let subcommand = args.first().and_then(|v| self.exit_codes.get(v));
+        exit_status(*subcommand.unwrap_or(&self.exit_code))
+    }
+}
+
+impl Runner for RecordingRunner {
+    fn run(&self, args: &[String], _out: &Output) -> anyhow::Result<ExitStatus> {
+        Ok(self.record(args, &[]))
+    }
+
+    fn run_target(
+        &self,
+        args: &[String],
+        env: &[(&str, &Path)],
+        _target: &str,
+        _out: &Output,
+    ) -> anyhow::Result<Option<ExitStatus>> {
+        Ok(Some(self.record(args, env)))
+    }
+
+    fn show_json(&self, plan_path: &str) -> anyhow::Result<serde_json::Value> {
+        self.plan_json
+            .clone()
+            .ok_or_else(|| ModuleError::ShowFailed(plan_path.to_string()).into())
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+fn exit_event(status: ExitStatus, start: Instant, out: &Output) {
+    out.event(Event::Exit {
+        success: status.success(),
+        code: status.code(),
+        signal: signal(status),
+        duration_ms: start.elapsed().as_millis(),
+    });
+}
+
+fn find_in_path(binary: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(binary))
+        .find(|path| path.is_file())
+}
+
+/// Subcommands that accept `-var-file`
+const VAR_FILE_COMMANDS: &[&str] = &["apply", "console", "destroy", "import", "plan", "refresh"];
+/// Subcommands that accept `-backend-config`
+const BACKEND_CONFIG_COMMANDS: &[&str] = &["init"];
+
+/// Subcommands that only read, and so don't need confirming on a protected environment
+const READ_ONLY_COMMANDS: &[&[&str]] = &[
+    &["plan"],
+    &["show"],
+    &["output"],
+    &["validate"],
+    &["state", "list"],
+    &["state", "show"],
+    &["providers"],
+    &["version"],
+];
+
+/// Whether the terraform arguments run a read-only subcommand. Anything else, e.g. `import` or
+/// `state rm`, may change the infrastructure or its state.
+pub fn read_only(args: &[String]) -> bool {
+    READ_ONLY_COMMANDS.iter().any(|command| {
+        args.len() >= command.len() && command.iter().zip(args).all(|(a, b)| a == b)
+    })
+}
+
+/// The var file flag to inject for a subcommand, as `(flag, var file basename)`.
+pub fn injected_var_file(subcommand: &str) -> Option<(&'static str, &'static str)> {
+    if VAR_FILE_COMMANDS.contains(&subcommand) {
+        Some(("-var-file", "terraform"))
+    } else if BACKEND_CONFIG_COMMANDS.contains(&subcommand) {
+        Some(("-backend-config", "backend"))
+    } else {
+        None
+    }
+}
+
+#[derive(Args, Default)]
+pub struct TerraformArgs {
+    /// Don't pass condeform's default flags (e.g. -lock-timeout) to terraform
+    #[arg(long)]
+    pub no_default_args: bool,
+    /// Extra arguments appended to the terraform command, replacing any default of the same name
+    #[arg(last = true)]
+    pub extra: Vec<String>,
+}
+
+/// Builds the argument list for a terraform subcommand.
+///
+/// `required` is always passed. `defaults` are dropped with `--no-default-args`, and any
+/// default whose flag name also appears in the pass-through arguments is replaced by it.
+pub fn build_args<S: AsRef<str>, D: AsRef<str>>(
+    required: &[S],
+    defaults: &[D],
+    passthrough: &TerraformArgs,
+) -> Vec<String> {
+    let mut args: Vec<String> = required.iter().map(|v| v.as_ref().to_string()).collect();
+
+    if !passthrough.no_default_args {
+        let overridden: Vec<&str> = passthrough.extra.iter().map(|v| flag_name(v)).collect();
+        args.extend(
+            defaults
+                .iter()
+                .map(|v| v.as_ref())
+                .filter(|v| !overridden.contains(&flag_name(v)))
+                .map(|v| v.to_string()),
+        );
+    }
+
+    args.extend(passthrough.extra.iter().cloned());
+    args
+}
+
+/// `-lock-timeout=30s` -> `-lock-timeout`
+fn flag_name(arg: &str) -> &str {
+    arg.split_once('=').map_or(arg, |(name, _)| name)
+}
+
+/// Maps terraform's exit status to condeform's, using the shell's 128 + signal convention when
+/// terraform was killed by a signal.
+pub fn exit_code(status: ExitStatus) -> ExitCode {
+    if let Some(code) = status.code() {
+        return ExitCode::from(code as u8);
+    }
+
+    match signal(status) {
+        Some(signal) => ExitCode::from(128u8.wrapping_add(signal as u8)),
+        None => ExitCode::FAILURE,
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
@@ -0,0 +1,535 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use condeform::matrix::{self, TargetResult};
+use condeform::{backend, commands};
+use condeform::error::ModuleError;
+use condeform::layout::Layout;
+use condeform::output::{Output, OutputFormat};
+use condeform::repo_config::RepoConfig;
+use condeform::resolve::get_module_var_files;
+use condeform::status::get_status;
+use condeform::terraform::{Invocation, RecordingRunner, TerraformArgs};
+use condeform::Config;
+use tempfile::TempDir;
+
+const OUT: Output = Output {
+    format: OutputFormat::Text,
+};
+
+/// An infra dir with `prod/us-east-1/vpc`, and `common.tfvars` at the top and in `prod`
+struct Fixture {
+    infra_dir: TempDir,
+    /// Stands in for `.terraform`, where init records its backend config
+    data_dir: TempDir,
+    config: Config,
+    layout: Layout,
+}
+
+impl Fixture {
+    fn new() -> Fixture {
+        let infra_dir = TempDir::new().unwrap();
+        let module_dir = infra_dir.path().join("prod/us-east-1/vpc");
+        fs::create_dir_all(&module_dir).unwrap();
+        for file in [
+            "common.tfvars",
+            "prod/common.tfvars",
+            "prod/us-east-1/vpc/backend.tfvars",
+            "prod/us-east-1/vpc/terraform.tfvars",
+        ] {
+            fs::write(infra_dir.path().join(file), "").unwrap();
+        }
+
+        let config = Config {
+            environment: Some("prod".to_string()),
+            region: "us-east-1".to_string(),
+            module: "vpc".to_string(),
+            infra_dir: infra_dir.path().to_str().unwrap().to_string(),
+            ..Config::default()
+        };
+        Fixture {
+            infra_dir,
+            data_dir: TempDir::new().unwrap(),
+            config,
+            layout: RepoConfig::default().layout().unwrap(),
+        }
+    }
+
+    fn path(&self, path: &str) -> String {
+        self.infra_dir.path().join(path).to_str().unwrap().to_string()
+    }
+
+    fn var_files(&self) -> Vec<PathBuf> {
+        get_module_var_files(&self.layout, &self.config).unwrap()
+    }
+}
+
+fn passthrough(extra: &[&str]) -> TerraformArgs {
+    TerraformArgs {
+        no_default_args: false,
+        extra: extra.iter().map(|v| v.to_string()).collect(),
+    }
+}
+
+#[test]
+fn init_passes_backend_config_and_default_flags() {
+    let fixture = Fixture::new();
+    let runner = RecordingRunner::default();
+
+    let status = commands::init(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        &fixture.config,
+        fixture.data_dir.path(),
+        &TerraformArgs::default(),
+    )
+    .unwrap();
+
+    assert!(status.success());
+    assert_eq!(
+        runner.args(),
+        [[
+            "init",
+            "-backend-config",
+            &fixture.path("prod/us-east-1/vpc/backend.tfvars"),
+            "-get=true",
+            "-force-copy",
+            "-reconfigure",
+        ]]
+    );
+}
+
+#[test]
+fn init_extra_args_replace_matching_defaults() {
+    let fixture = Fixture::new();
+    let runner = RecordingRunner::default();
+
+    commands::init(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        &fixture.config,
+        fixture.data_dir.path(),
+        &passthrough(&["-get=false", "-upgrade"]),
+    )
+    .unwrap();
+
+    assert_eq!(
+        runner.args(),
+        [[
+            "init",
+            "-backend-config",
+            &fixture.path("prod/us-east-1/vpc/backend.tfvars"),
+            "-force-copy",
+            "-reconfigure",
+            "-get=false",
+            "-upgrade",
+        ]]
+    );
+}
+
+#[test]
+fn init_reports_terraform_failure() {
+    let fixture = Fixture::new();
+    let runner = RecordingRunner {
+        exit_code: 1,
+        ..RecordingRunner::default()
+    };
+
+    let status = commands::init(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        &fixture.config,
+        fixture.data_dir.path(),
+        &TerraformArgs::default(),
+    )
+    .unwrap();
+
+    assert_eq!(status.code(), Some(1));
+}
+
+#[test]
+fn init_records_the_backend_config_in_the_data_dir() {
+    let fixture = Fixture::new();
+    let data_dir = fixture.data_dir.path();
+    let backend_config = fixture.path("prod/us-east-1/vpc/backend.tfvars");
+
+    commands::init(
+        &RecordingRunner::default(),
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        &fixture.config,
+        data_dir,
+        &TerraformArgs::default(),
+    )
+    .unwrap();
+
+    assert!(backend::check_init(data_dir, Path::new(&backend_config)).is_ok());
+    // the same file through another path, as with a relative infra dir
+    let other_path = fixture.path("prod/../prod/us-east-1/vpc/backend.tfvars");
+    assert!(backend::check_init(data_dir, Path::new(&other_path)).is_ok());
+    assert!(matches!(
+        backend::check_init(data_dir, Path::new(&fixture.path("common.tfvars"))),
+        Err(ModuleError::BackendChanged { .. })
+    ));
+}
+
+#[test]
+fn failed_init_forgets_the_backend_config() {
+    let fixture = Fixture::new();
+    let data_dir = fixture.data_dir.path();
+    backend::write_init_marker(data_dir, Path::new(&fixture.path("common.tfvars"))).unwrap();
+    let runner = RecordingRunner {
+        exit_code: 1,
+        ..RecordingRunner::default()
+    };
+
+    commands::init(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        &fixture.config,
+        data_dir,
+        &TerraformArgs::default(),
+    )
+    .unwrap();
+
+    assert!(backend::read_init_marker(data_dir).is_none());
+}
+
+#[test]
+fn init_fails_without_module_dir() {
+    let fixture = Fixture::new();
+    let runner = RecordingRunner::default();
+    let config = Config {
+        environment: Some("stage".to_string()),
+        ..fixture.config.clone()
+    };
+
+    let err = commands::init(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        &config,
+        fixture.data_dir.path(),
+        &TerraformArgs::default(),
+    )
+    .unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref::<ModuleError>(),
+        Some(ModuleError::NotADirectory(_))
+    ));
+    assert!(runner.args().is_empty());
+}
+
+#[test]
+fn plan_layers_common_var_files_before_the_module_var_file() {
+    let fixture = Fixture::new();
+    let runner = RecordingRunner::default();
+
+    commands::plan(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        &fixture.config,
+        &fixture.path("plan.plan"),
+        false,
+        None,
+        &TerraformArgs::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        runner.args(),
+        [[
+            "plan",
+            "-var-file",
+            &fixture.path("common.tfvars"),
+            "-var-file",
+            &fixture.path("prod/common.tfvars"),
+            "-var-file",
+            &fixture.path("prod/us-east-1/vpc/terraform.tfvars"),
+            &format!("-out={}", fixture.path("plan.plan")),
+            "-lock-timeout=30s",
+        ]]
+    );
+}
+
+#[test]
+fn plan_with_detailed_exitcode_and_repo_default_args() {
+    let fixture = Fixture::new();
+    fs::remove_file(fixture.path("common.tfvars")).unwrap();
+    fs::remove_file(fixture.path("prod/common.tfvars")).unwrap();
+    let runner = RecordingRunner {
+        exit_code: 2,
+        ..RecordingRunner::default()
+    };
+    let repo_config = RepoConfig {
+        args: [("plan".to_string(), vec!["-parallelism=5".to_string()])].into(),
+        ..RepoConfig::default()
+    };
+
+    let (status, summary) = commands::plan(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &repo_config,
+        &fixture.config,
+        &fixture.path("plan.plan"),
+        true,
+        None,
+        &passthrough(&["-target=aws_vpc.main"]),
+    )
+    .unwrap();
+
+    assert_eq!(status.code(), Some(2));
+    // the plan succeeded, but without `show -json` output it can't be summarized
+    assert!(summary.is_none());
+    assert_eq!(
+        runner.args(),
+        [[
+            "plan",
+            "-var-file",
+            &fixture.path("prod/us-east-1/vpc/terraform.tfvars"),
+            &format!("-out={}", fixture.path("plan.plan")),
+            "-detailed-exitcode",
+            "-parallelism=5",
+            "-target=aws_vpc.main",
+        ]]
+    );
+}
+
+#[test]
+fn plan_writes_its_manifest_and_summary() {
+    let fixture = Fixture::new();
+    let runner = RecordingRunner {
+        plan_json: Some(serde_json::json!({
+            "resource_changes": [
+                {"address": "aws_vpc.main", "change": {"actions": ["create"]}},
+            ],
+        })),
+        ..RecordingRunner::default()
+    };
+    let markdown = fixture.infra_dir.path().join("plan.md");
+
+    let (status, summary) = commands::plan(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        &fixture.config,
+        &fixture.path("plan.plan"),
+        false,
+        Some(&markdown),
+        &TerraformArgs::default(),
+    )
+    .unwrap();
+
+    assert!(status.success());
+    assert_eq!(summary.unwrap().create, ["aws_vpc.main"]);
+    assert!(fs::read_to_string(markdown).unwrap().contains("- `aws_vpc.main`"));
+    assert!(Path::new(&fixture.path("plan.plan.toml")).is_file());
+}
+
+#[test]
+fn matrix_target_inits_and_plans_in_its_own_data_dir() {
+    let fixture = Fixture::new();
+    let work_dir = TempDir::new().unwrap();
+    let runner = RecordingRunner {
+        exit_codes: [("plan".to_string(), 2)].into(),
+        ..RecordingRunner::default()
+    };
+
+    let results = matrix::plan_matrix(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        work_dir.path(),
+        std::slice::from_ref(&fixture.config),
+        1,
+        &TerraformArgs::default(),
+    )
+    .unwrap();
+
+    assert!(matches!(results[0].result, TargetResult::Changes));
+    assert!(work_dir.path().join(".condeform/prod_us-east-1_vpc").is_dir());
+    let data_dir = PathBuf::from(".condeform/prod_us-east-1_vpc/data");
+    assert_eq!(
+        *runner.invocations.lock().unwrap(),
+        [
+            Invocation {
+                args: vec![
+                    "init".to_string(),
+                    "-backend-config".to_string(),
+                    fixture.path("prod/us-east-1/vpc/backend.tfvars"),
+                    "-get=true".to_string(),
+                    "-force-copy".to_string(),
+                    "-reconfigure".to_string(),
+                ],
+                env: vec![("TF_DATA_DIR".to_string(), data_dir.clone())],
+            },
+            Invocation {
+                args: vec![
+                    "plan".to_string(),
+                    "-var-file".to_string(),
+                    fixture.path("common.tfvars"),
+                    "-var-file".to_string(),
+                    fixture.path("prod/common.tfvars"),
+                    "-var-file".to_string(),
+                    fixture.path("prod/us-east-1/vpc/terraform.tfvars"),
+                    "-out=.condeform/prod_us-east-1_vpc/plan.plan".to_string(),
+                    "-detailed-exitcode".to_string(),
+                    "-lock-timeout=30s".to_string(),
+                ],
+                env: vec![("TF_DATA_DIR".to_string(), data_dir)],
+            },
+        ]
+    );
+}
+
+#[test]
+fn destroy_passes_var_files_and_no_default_flags() {
+    let fixture = Fixture::new();
+    let runner = RecordingRunner::default();
+
+    commands::destroy(
+        &runner,
+        &OUT,
+        &RepoConfig::default(),
+        &fixture.var_files(),
+        &passthrough(&["-auto-approve"]),
+    )
+    .unwrap();
+
+    assert_eq!(
+        runner.args(),
+        [[
+            "destroy",
+            "-var-file",
+            &fixture.path("common.tfvars"),
+            "-var-file",
+            &fixture.path("prod/common.tfvars"),
+            "-var-file",
+            &fixture.path("prod/us-east-1/vpc/terraform.tfvars"),
+            "-auto-approve",
+        ]]
+    );
+}
+
+#[test]
+fn destroy_with_no_default_args_drops_repo_defaults() {
+    let fixture = Fixture::new();
+    let runner = RecordingRunner::default();
+    let repo_config = RepoConfig {
+        args: [("destroy".to_string(), vec!["-lock-timeout=1m".to_string()])].into(),
+        ..RepoConfig::default()
+    };
+
+    commands::destroy(
+        &runner,
+        &OUT,
+        &repo_config,
+        &fixture.var_files()[2..],
+        &TerraformArgs {
+            no_default_args: true,
+            extra: vec![],
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        runner.args(),
+        [[
+            "destroy",
+            "-var-file",
+            &fixture.path("prod/us-east-1/vpc/terraform.tfvars"),
+        ]]
+    );
+}
+
+#[test]
+fn module_var_files_require_the_module_dir() {
+    let fixture = Fixture::new();
+    fs::remove_dir_all(Path::new(&fixture.path("prod/us-east-1/vpc"))).unwrap();
+
+    assert!(matches!(
+        get_module_var_files(&fixture.layout, &fixture.config),
+        Err(ModuleError::NotADirectory(_))
+    ));
+}
+
+#[test]
+fn init_fails_without_backend_config() {
+    let fixture = Fixture::new();
+    fs::remove_file(fixture.path("prod/us-east-1/vpc/backend.tfvars")).unwrap();
+    let runner = RecordingRunner::default();
+
+    let err = commands::init(
+        &runner,
+        &OUT,
+        &fixture.layout,
+        &RepoConfig::default(),
+        &fixture.config,
+        fixture.data_dir.path(),
+        &TerraformArgs::default(),
+    )
+    .unwrap_err();
+
+    let err = err.downcast_ref::<ModuleError>().unwrap();
+    assert!(matches!(err, ModuleError::MissingVarFile(_)));
+    assert_eq!(err.exit_code(), condeform::error::EXIT_ENVIRONMENT);
+    assert!(runner.args().is_empty());
+}
+
+#[test]
+fn module_var_files_require_the_infra_dir() {
+    let fixture = Fixture::new();
+    let config = Config {
+        infra_dir: fixture.path("missing"),
+        ..fixture.config.clone()
+    };
+
+    assert!(matches!(
+        get_module_var_files(&fixture.layout, &config),
+        Err(ModuleError::MissingInfraDir(_))
+    ));
+}
+
+#[test]
+fn status_shows_an_incomplete_target() {
+    let fixture = Fixture::new();
+    let config = Config {
+        environment: None,
+        ..fixture.config.clone()
+    };
+
+    let status = get_status(
+        &fixture.layout,
+        &config,
+        Path::new("repo.toml"),
+        fixture.data_dir.path(),
+    )
+    .unwrap();
+
+    assert_eq!(status.target, "<none>/us-east-1/vpc");
+    assert_eq!(
+        status.backend_config.path,
+        Path::new(&fixture.path("<none>/us-east-1/vpc/backend.tfvars"))
+    );
+    assert!(!status.backend_config.exists);
+    // only the infra dir's common.tfvars is above the unset environment
+    assert_eq!(status.var_files.len(), 2);
+    assert!(status.var_files[0].exists);
+    assert!(!status.var_files[1].exists);
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use condeform::error::ModuleError;
+use condeform::repo::Repo;
+use condeform::state;
+use tempfile::TempDir;
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args([
+            "-c",
+            "user.name=condeform",
+            "-c",
+            "user.email=condeform@example.com",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+/// A git repo with one commit and a `module` directory, so that worktrees can be added
+fn git_repo() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("module")).unwrap();
+    fs::write(dir.path().join("module/main.tf"), "").unwrap();
+    git(dir.path(), &["init", "-q"]);
+    git(dir.path(), &["add", "."]);
+    git(dir.path(), &["commit", "-qm", "init"]);
+    dir
+}
+
+#[test]
+fn git_repo_is_keyed_on_its_root() {
+    let dir = git_repo();
+    let root = dir.path().canonicalize().unwrap();
+
+    let repo = Repo::find(&dir.path().join("module"), None).unwrap();
+
+    assert_eq!(repo.root, root);
+    assert_eq!(repo.state_key, root.to_str().unwrap());
+}
+
+#[test]
+fn worktree_is_keyed_on_the_main_checkout_and_its_name() {
+    let dir = git_repo();
+    let worktrees = TempDir::new().unwrap();
+    let worktree = worktrees.path().join("feature");
+    git(
+        dir.path(),
+        &["worktree", "add", "-q", worktree.to_str().unwrap()],
+    );
+
+    let repo = Repo::find(&worktree.join("module"), None).unwrap();
+
+    assert_eq!(repo.root, worktree.canonicalize().unwrap());
+    let main = dir.path().canonicalize().unwrap();
+    assert_eq!(
+        repo.state_key,
+        format!("{}#feature", main.to_str().unwrap())
+    );
+}
+
+#[test]
+fn legacy_worktree_state_is_rekeyed_on_the_main_checkout() {
+    let dir = git_repo();
+    let worktrees = TempDir::new().unwrap();
+    let worktree = worktrees.path().join("feature");
+    git(
+        dir.path(),
+        &["worktree", "add", "-q", worktree.to_str().unwrap()],
+    );
+    let worktree = worktree.canonicalize().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    // before worktrees were keyed on the main checkout, the key was the worktree's own path
+    let legacy_name = format!("{}.toml", worktree.to_str().unwrap().replace('/', "%"));
+    fs::write(state_dir.path().join(legacy_name), "").unwrap();
+
+    let index = state::read_index(state_dir.path()).unwrap();
+
+    let repo = Repo::find(&worktree, None).unwrap();
+    let entry = &index.repos[&state::state_filename(&repo.state_key)];
+    assert_eq!(entry.state_key, repo.state_key);
+    assert_eq!(entry.root, worktree);
+}
+
+#[test]
+fn outside_git_the_repo_is_the_nearest_marker() {
+    let dir = TempDir::new().unwrap();
+    let module = dir.path().join("infra/module");
+    fs::create_dir_all(module.join(".terraform")).unwrap();
+    fs::write(dir.path().join(".condeform.toml"), "").unwrap();
+
+    let repo = Repo::find(&module, None).unwrap();
+
+    // `.condeform.toml` is preferred over the nearer `.terraform`
+    assert_eq!(repo.root, dir.path().canonicalize().unwrap());
+
+    fs::remove_file(dir.path().join(".condeform.toml")).unwrap();
+    let repo = Repo::find(&module, None).unwrap();
+
+    assert_eq!(repo.root, module.canonicalize().unwrap());
+}
+
+#[test]
+fn no_repo_outside_git_without_a_marker() {
+    let dir = TempDir::new().unwrap();
+
+    assert!(matches!(
+        Repo::find(dir.path(), None),
+        Err(ModuleError::RepoNotFound(_))
+    ));
+}
+
+#[test]
+fn state_key_replaces_the_derived_key() {
+    let dir = git_repo();
+
+    let repo = Repo::find(dir.path(), Some("shared")).unwrap();
+
+    assert_eq!(repo.root, dir.path().canonicalize().unwrap());
+    assert_eq!(repo.state_key, "shared");
+}
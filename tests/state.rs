@@ -0,0 +1,125 @@
+use std::fs;
+
+use condeform::error::{ModuleError, EXIT_STATE};
+use condeform::repo::Repo;
+use condeform::state::{
+    list, prune, read_index, read_state, repo_state_path, state_filename, write_state, State,
+};
+use condeform::Config;
+use tempfile::TempDir;
+
+#[test]
+fn missing_state_file_is_empty_state() {
+    let dir = TempDir::new().unwrap();
+
+    let state = read_state(&dir.path().join("repo.toml")).unwrap();
+
+    assert!(state.defaults.is_none());
+    assert!(state.modules.is_empty());
+}
+
+#[test]
+fn state_round_trips() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("repo.toml");
+    let mut state = State::default();
+    state.set("infra/terraform/vpc", &Config::default());
+
+    write_state(&path, &state).unwrap();
+    let state = read_state(&path).unwrap();
+
+    assert_eq!(state.modules["infra/terraform/vpc"].module, "vpc");
+    assert_eq!(state.defaults.unwrap().region, "us-east-1");
+}
+
+#[test]
+fn state_with_terraform_bin_round_trips() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("repo.toml");
+    let mut state = State {
+        terraform_bin: Some("tofu".to_string()),
+        ..State::default()
+    };
+    state.set("infra/terraform/vpc", &Config::default());
+
+    write_state(&path, &state).unwrap();
+    let state = read_state(&path).unwrap();
+
+    assert_eq!(state.terraform_bin.as_deref(), Some("tofu"));
+    assert_eq!(state.modules["infra/terraform/vpc"].module, "vpc");
+}
+
+#[test]
+fn corrupt_state_file_is_an_error() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("repo.toml");
+    fs::write(&path, "[modules\nenvironment = ").unwrap();
+
+    let err = read_state(&path).unwrap_err();
+
+    let err = err.downcast_ref::<ModuleError>().unwrap();
+    assert!(matches!(err, ModuleError::CorruptState { .. }));
+    assert_eq!(err.exit_code(), EXIT_STATE);
+}
+
+#[test]
+fn state_filenames_are_readable_and_distinct() {
+    // both were `%a%b.toml` when `/` was replaced by `%`
+    let name = state_filename("/a/b");
+
+    assert!(name.starts_with("b-"));
+    assert!(name.ends_with(".toml"));
+    assert_ne!(name, state_filename("/a%b"));
+}
+
+#[test]
+fn legacy_state_file_is_renamed_and_indexed() {
+    let state_dir = TempDir::new().unwrap();
+    let root = TempDir::new().unwrap();
+    let state_key = root.path().to_str().unwrap().to_string();
+    fs::write(
+        state_dir
+            .path()
+            .join(format!("{}.toml", state_key.replace('/', "%"))),
+        "[modules.vpc]\nregion = \"eu-west-1\"\nmodule = \"vpc\"\ninfra_dir = \"envs\"\n",
+    )
+    .unwrap();
+    let repo = Repo {
+        root: root.path().to_path_buf(),
+        state_key: state_key.to_owned(),
+    };
+
+    let state_path = repo_state_path(state_dir.path(), &repo).unwrap();
+
+    assert_eq!(
+        read_state(&state_path).unwrap().modules["vpc"].region,
+        "eu-west-1"
+    );
+    let index = read_index(state_dir.path()).unwrap();
+    assert_eq!(index.repos[&state_filename(&state_key)].root, root.path());
+}
+
+#[test]
+fn prune_removes_state_of_missing_repos() {
+    let state_dir = TempDir::new().unwrap();
+    let root = TempDir::new().unwrap();
+    let mut paths = vec![];
+    for root in [root.path().to_path_buf(), root.path().join("deleted")] {
+        let repo = Repo {
+            state_key: root.to_str().unwrap().to_string(),
+            root,
+        };
+        let state_path = repo_state_path(state_dir.path(), &repo).unwrap();
+        write_state(&state_path, &State::default()).unwrap();
+        paths.push(state_path);
+    }
+
+    assert_eq!(prune(state_dir.path(), true).unwrap().len(), 1);
+    assert!(paths[1].exists());
+    let pruned = prune(state_dir.path(), false).unwrap();
+
+    assert_eq!(pruned[0].path, paths[1]);
+    assert!(!paths[1].exists());
+    assert!(paths[0].exists());
+    assert_eq!(list(state_dir.path()).unwrap().len(), 1);
+}
@@ -0,0 +1,21 @@
+use condeform::terraform::read_only;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|v| v.to_string()).collect()
+}
+
+#[test]
+fn read_only_subcommands() {
+    assert!(read_only(&args(&["plan", "-out=plan.plan"])));
+    assert!(read_only(&args(&["state", "list"])));
+    assert!(read_only(&args(&["state", "show", "aws_vpc.main"])));
+}
+
+#[test]
+fn subcommands_that_change_state_are_not_read_only() {
+    assert!(!read_only(&args(&["apply"])));
+    assert!(!read_only(&args(&["import", "aws_vpc.main", "vpc-0123"])));
+    assert!(!read_only(&args(&["state", "rm", "aws_vpc.main"])));
+    assert!(!read_only(&args(&["state"])));
+    assert!(!read_only(&args(&["force-unlock", "1234"])));
+}